@@ -52,7 +52,9 @@ fn main() {
     client.borrow_mut().set_timeout(config.http.timeout);
 
     let mut executor = Executor::new();
-    if PathBuf::from("/var/log/containers/").exists() {
+    // the host's logs may be mounted elsewhere and reported under their host paths
+    let containers = PathBuf::from("/var/log/containers/");
+    if config.log.tail.path_map.watched(&containers).exists() {
         match K8sMetadata::new() {
             Ok(v) => executor.register(v),
             Err(e) => warn!("{}", e),
@@ -61,7 +63,11 @@ fn main() {
 
     let mut source_reader = SourceReader::new();
     register_journald_source(&mut source_reader);
-    source_reader.register(FSSource::new(config.log.dirs, config.log.rules));
+    source_reader.register(FSSource::new(
+        config.log.dirs,
+        config.log.rules,
+        config.log.tail,
+    ));
 
     executor.init();
 
@@ -58,6 +58,74 @@ pub struct Config {
     #[example("/var/log/,/var/data/,/test/logs/")]
     pub log_dirs: Option<EnvList<PathBuf>>,
 
+    #[env(LOGDNA_DB_PATH)]
+    #[example("/var/lib/logdna/")]
+    pub db_path: Option<PathBuf>,
+
+    #[env(LOGDNA_INITIAL_POSITION)]
+    #[example("lookback(2h)")]
+    pub initial_position: Option<String>,
+
+    #[env(LOGDNA_POLL_DIRS)]
+    #[example("/mnt/nfs/logs/")]
+    pub poll_dirs: Option<EnvList<PathBuf>>,
+
+    #[env(LOGDNA_POLL_INTERVAL)]
+    #[example("1000")]
+    pub poll_interval: Option<u64>,
+
+    #[env(LOGDNA_SCAN_THREADS)]
+    #[example("4")]
+    pub scan_threads: Option<usize>,
+
+    #[env(LOGDNA_SCAN_MAX_DEPTH)]
+    #[example("16")]
+    pub scan_max_depth: Option<usize>,
+
+    #[env(LOGDNA_SCAN_MAX_ENTRIES)]
+    #[example("1000000")]
+    pub scan_max_entries: Option<usize>,
+
+    #[env(LOGDNA_MAX_LINE_BYTES)]
+    #[example("1048576")]
+    pub max_line_bytes: Option<usize>,
+
+    #[env(LOGDNA_OVERSIZE_LINE_MODE)]
+    #[example("split")]
+    pub oversize_line_mode: Option<String>,
+
+    #[env(LOGDNA_READ_BUDGET_BYTES)]
+    #[example("1048576")]
+    pub read_budget_bytes: Option<u64>,
+
+    #[env(LOGDNA_READ_BUDGET_LINES)]
+    #[example("10000")]
+    pub read_budget_lines: Option<u64>,
+
+    #[env(LOGDNA_FILE_IDLE_TIMEOUT)]
+    #[example("300")]
+    pub file_idle_timeout: Option<u64>,
+
+    #[env(LOGDNA_MAX_OPEN_FILES)]
+    #[example("1024")]
+    pub max_open_files: Option<usize>,
+
+    #[env(LOGDNA_DELETE_GRACE_PERIOD)]
+    #[example("30")]
+    pub delete_grace_period: Option<u64>,
+
+    #[env(LOGDNA_DEDUPE_LINKS)]
+    #[example("true")]
+    pub dedupe_links: Option<bool>,
+
+    #[env(LOGDNA_PROC_DISCOVERY_INTERVAL)]
+    #[example("10")]
+    pub proc_discovery_interval: Option<u64>,
+
+    #[env(LOGDNA_PATH_MAP)]
+    #[example("/host/var/log=/var/log")]
+    pub path_map: Option<EnvList<String>>,
+
     #[env(LOGDNA_EXCLUSION_RULES, LOGDNA_EXCLUDE)]
     #[example("/var/log/**,/var/data/**")]
     pub exclusion_rules: Option<EnvList<String>>,
@@ -73,6 +141,10 @@ pub struct Config {
     #[env(LOGDNA_INCLUSION_REGEX_RULES, LOGDNA_INCLUDE_REGEX)]
     #[example("/var/log/.*,/var/data/.*")]
     pub inclusion_regex_rules: Option<EnvList<String>>,
+
+    #[env(LOGDNA_BINARY_INCLUSION_RULES)]
+    #[example("/var/log/app/*.log")]
+    pub binary_inclusion_rules: Option<EnvList<String>>,
 }
 
 impl Config {
@@ -139,6 +211,77 @@ impl Config {
             raw.log.dirs.append(&mut v)
         }
 
+        if self.db_path.is_some() {
+            raw.log.db_path = self.db_path;
+        }
+
+        if self.initial_position.is_some() {
+            raw.log.initial_position = self.initial_position;
+        }
+
+        if let Some(mut v) = self.poll_dirs {
+            raw.log
+                .poll_dirs
+                .get_or_insert_with(Vec::new)
+                .append(&mut v);
+        }
+
+        if self.poll_interval.is_some() {
+            raw.log.poll_interval = self.poll_interval;
+        }
+
+        if self.scan_threads.is_some() {
+            raw.log.scan_threads = self.scan_threads;
+        }
+
+        if self.scan_max_depth.is_some() {
+            raw.log.scan_max_depth = self.scan_max_depth;
+        }
+
+        if self.scan_max_entries.is_some() {
+            raw.log.scan_max_entries = self.scan_max_entries;
+        }
+
+        if self.max_line_bytes.is_some() {
+            raw.log.max_line_bytes = self.max_line_bytes;
+        }
+
+        if self.oversize_line_mode.is_some() {
+            raw.log.oversize_line_mode = self.oversize_line_mode;
+        }
+
+        if self.read_budget_bytes.is_some() {
+            raw.log.read_budget_bytes = self.read_budget_bytes;
+        }
+
+        if self.read_budget_lines.is_some() {
+            raw.log.read_budget_lines = self.read_budget_lines;
+        }
+
+        if self.file_idle_timeout.is_some() {
+            raw.log.file_idle_timeout = self.file_idle_timeout;
+        }
+
+        if self.max_open_files.is_some() {
+            raw.log.max_open_files = self.max_open_files;
+        }
+
+        if self.delete_grace_period.is_some() {
+            raw.log.delete_grace_period = self.delete_grace_period;
+        }
+
+        if self.dedupe_links.is_some() {
+            raw.log.dedupe_links = self.dedupe_links;
+        }
+
+        if self.proc_discovery_interval.is_some() {
+            raw.log.proc_discovery_interval = self.proc_discovery_interval;
+        }
+
+        if let Some(mut v) = self.path_map {
+            raw.log.path_map.get_or_insert_with(Vec::new).append(&mut v);
+        }
+
         if let Some(mut v) = self.exclusion_rules {
             match raw.log.exclude {
                 Some(ref mut rules) => rules.glob.append(&mut v),
@@ -195,6 +338,20 @@ impl Config {
             }
         }
 
+        if let Some(mut v) = self.binary_inclusion_rules {
+            match raw.log.binary_include {
+                Some(ref mut rules) => rules.glob.append(&mut v),
+                None => {
+                    let mut rules = RawRules {
+                        glob: Vec::new(),
+                        regex: Vec::new(),
+                    };
+                    rules.glob.append(&mut v);
+                    raw.log.binary_include = Some(rules);
+                }
+            }
+        }
+
         raw
     }
 }
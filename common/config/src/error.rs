@@ -5,6 +5,7 @@ use std::{fmt, io};
 pub enum ConfigError {
     MissingField(&'static str),
     MissingFieldOrEnvVar(&'static str, Vec<String>),
+    InvalidField(&'static str, String),
     Io(io::Error),
     Serde(serde_yaml::Error),
     Template(http::types::error::TemplateError),
@@ -24,6 +25,9 @@ impl Display for ConfigError {
                     field, vars
                 )
             }
+            ConfigError::InvalidField(field, reason) => {
+                write!(f, "{} is invalid: {}", field, reason)
+            }
             ConfigError::Io(e) => write!(f, "{}", e),
             ConfigError::Serde(e) => write!(f, "{}", e),
             ConfigError::Template(e) => write!(f, "{}", e),
@@ -10,7 +10,16 @@ use std::time::Duration;
 
 use flate2::Compression;
 
+use fs::cache::scan::ScanConfig;
+use fs::cache::watch::WatchConfig;
+use fs::discovery::DiscoveryConfig;
+use fs::encoding::EncodingRule;
+use fs::fields::PathFieldRule;
+use fs::format::FormatRule;
+use fs::framing::{Framing, FramingRule};
+use fs::multiline::MultilineRule;
 use fs::rule::{GlobRule, RegexRule, Rules};
+use fs::tail::{LineLimit, OversizeMode, ReadBudget, TailConfig};
 use http::types::request::{Encoding, RequestTemplate, Schema};
 
 use crate::env::Config as EnvConfig;
@@ -38,6 +47,7 @@ pub struct HttpConfig {
 pub struct LogConfig {
     pub dirs: Vec<PathBuf>,
     pub rules: Rules,
+    pub tail: TailConfig,
 }
 
 impl Config {
@@ -160,8 +170,71 @@ impl TryFrom<RawConfig> for Config {
         let mut log = LogConfig {
             dirs: raw.log.dirs.into_iter().collect(),
             rules: Rules::new(),
+            tail: TailConfig {
+                db_path: raw.log.db_path,
+                initial_position: match raw.log.initial_position {
+                    Some(position) => position
+                        .parse()
+                        .map_err(|e| ConfigError::InvalidField("log.initial_position", e))?,
+                    None => Default::default(),
+                },
+                watch: WatchConfig {
+                    poll_dirs: raw.log.poll_dirs.unwrap_or_default(),
+                    poll_interval: match raw.log.poll_interval {
+                        Some(interval) => Duration::from_millis(interval),
+                        None => WatchConfig::default().poll_interval,
+                    },
+                    scan: ScanConfig {
+                        threads: match raw.log.scan_threads {
+                            Some(0) => {
+                                return Err(ConfigError::InvalidField(
+                                    "log.scan_threads",
+                                    "must be at least 1".into(),
+                                ))
+                            }
+                            Some(threads) => threads,
+                            None => ScanConfig::default().threads,
+                        },
+                        max_depth: raw.log.scan_max_depth,
+                        max_entries: raw.log.scan_max_entries,
+                    },
+                },
+                line_limit: match raw.log.max_line_bytes {
+                    Some(max_bytes) => Some(LineLimit {
+                        max_bytes,
+                        mode: match raw.log.oversize_line_mode {
+                            Some(mode) => mode.parse().map_err(|e| {
+                                ConfigError::InvalidField("log.oversize_line_mode", e)
+                            })?,
+                            None => OversizeMode::Truncate,
+                        },
+                    }),
+                    None => None,
+                },
+                budget: ReadBudget {
+                    bytes: raw.log.read_budget_bytes,
+                    lines: raw.log.read_budget_lines,
+                },
+                idle_timeout: raw.log.file_idle_timeout.map(Duration::from_secs),
+                max_open_files: raw.log.max_open_files,
+                delete_grace: raw.log.delete_grace_period.map(Duration::from_secs),
+                dedupe_links: raw.log.dedupe_links.unwrap_or(false),
+                discovery: raw
+                    .log
+                    .proc_discovery_interval
+                    .map(|interval| DiscoveryConfig {
+                        interval: Duration::from_secs(interval),
+                        ..Default::default()
+                    }),
+                ..Default::default()
+            },
         };
 
+        // dirs can contain wildcards, in the same syntax as the glob rules
+        for dir in log.dirs.iter() {
+            GlobRule::new(&*dir.to_string_lossy())?;
+        }
+
         if let Some(rules) = raw.log.include {
             for glob in rules.glob {
                 log.rules.add_inclusion(GlobRule::new(&*glob)?)
@@ -182,6 +255,116 @@ impl TryFrom<RawConfig> for Config {
             }
         }
 
+        if let Some(rules) = raw.log.binary_include {
+            for glob in rules.glob {
+                log.tail
+                    .binary_include
+                    .add_inclusion(GlobRule::new(&*glob)?)
+            }
+
+            for regex in rules.regex {
+                log.tail
+                    .binary_include
+                    .add_inclusion(RegexRule::new(&*regex)?)
+            }
+        }
+
+        for rule in raw.log.path_map.unwrap_or_default() {
+            log.tail
+                .path_map
+                .add_rule(&rule)
+                .map_err(|e| ConfigError::InvalidField("log.path_map", e))?;
+        }
+
+        for rule in raw.log.multiline.unwrap_or_default() {
+            let mut rules = Rules::new();
+            for glob in rule.glob {
+                rules.add_inclusion(GlobRule::new(&*glob)?)
+            }
+
+            for regex in rule.regex {
+                rules.add_inclusion(RegexRule::new(&*regex)?)
+            }
+
+            let mut multiline =
+                MultilineRule::new(rules, rule.start.as_deref(), rule.continuation.as_deref())?;
+            if let Some(max_lines) = rule.max_lines {
+                multiline = multiline.max_lines(max_lines);
+            }
+            if let Some(timeout) = rule.timeout {
+                multiline = multiline.timeout(Duration::from_millis(timeout));
+            }
+            log.tail.multiline.push(multiline);
+        }
+
+        for rule in raw.log.encoding.unwrap_or_default() {
+            let mut rules = Rules::new();
+            for glob in rule.glob {
+                rules.add_inclusion(GlobRule::new(&*glob)?)
+            }
+
+            for regex in rule.regex {
+                rules.add_inclusion(RegexRule::new(&*regex)?)
+            }
+
+            log.tail.encodings.push(
+                EncodingRule::new(rules, &rule.encoding)
+                    .map_err(|e| ConfigError::InvalidField("log.encoding", e))?,
+            );
+        }
+
+        for rule in raw.log.format.unwrap_or_default() {
+            let mut rules = Rules::new();
+            for glob in rule.glob {
+                rules.add_inclusion(GlobRule::new(&*glob)?)
+            }
+
+            for regex in rule.regex {
+                rules.add_inclusion(RegexRule::new(&*regex)?)
+            }
+
+            let format = rule
+                .format
+                .parse()
+                .map_err(|e| ConfigError::InvalidField("log.format", e))?;
+            log.tail.formats.push(FormatRule::new(rules, format));
+        }
+
+        for rule in raw.log.framing.unwrap_or_default() {
+            let mut rules = Rules::new();
+            for glob in rule.glob {
+                rules.add_inclusion(GlobRule::new(&*glob)?)
+            }
+
+            for regex in rule.regex {
+                rules.add_inclusion(RegexRule::new(&*regex)?)
+            }
+
+            // a custom delimiter is given on it's own, without naming the framing
+            let framing = match (rule.framing, rule.delimiter) {
+                (None, Some(delimiter)) => Framing::delimiter(delimiter),
+                (Some(framing), None) => framing.parse(),
+                _ => Err("expected either a framing or a delimiter".to_string()),
+            }
+            .map_err(|e| ConfigError::InvalidField("log.framing", e))?;
+            log.tail.framing.push(FramingRule::new(rules, framing));
+        }
+
+        for rule in raw.log.path_fields.unwrap_or_default() {
+            let mut fields = Vec::new();
+            for (name, field) in rule.fields {
+                let field = field
+                    .parse()
+                    .map_err(|e| ConfigError::InvalidField("log.path_fields", e))?;
+                fields.push((name, field));
+            }
+
+            log.tail.path_fields.push(
+                PathFieldRule::new(&rule.regex, fields)
+                    .map_err(|e| ConfigError::InvalidField("log.path_fields", e))?,
+            );
+        }
+
         Ok(Config { http, log })
     }
 }
@@ -220,6 +403,7 @@ mod tests {
     use std::env;
     use std::fs::{remove_file, OpenOptions};
 
+    use fs::tail::InitialPosition;
     use scopeguard::guard;
 
     use super::*;
@@ -238,6 +422,220 @@ mod tests {
         assert!(Config::try_from(raw).is_ok());
     }
 
+    // converts a valid raw config with it's log section changed by update
+    fn log_config(update: impl FnOnce(&mut raw::LogConfig)) -> Result<Config, ConfigError> {
+        let mut raw = RawConfig::default();
+        raw.http.ingestion_key = Some("emptyingestionkey".to_string());
+        update(&mut raw.log);
+        Config::try_from(raw)
+    }
+
+    // the field an InvalidField error is for, panics on anything else
+    fn invalid_field(result: Result<Config, ConfigError>) -> &'static str {
+        match result {
+            Err(ConfigError::InvalidField(field, _)) => field,
+            other => panic!("expected an invalid field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_initial_position() {
+        let cases = vec![
+            (None, Some(InitialPosition::Threshold(8192))),
+            (Some("start"), Some(InitialPosition::Start)),
+            (Some(" END "), Some(InitialPosition::End)),
+            (
+                Some("threshold(1024)"),
+                Some(InitialPosition::Threshold(1024)),
+            ),
+            (
+                Some("lookback(90)"),
+                Some(InitialPosition::Lookback(Duration::from_secs(90))),
+            ),
+            (
+                Some("lookback(2h)"),
+                Some(InitialPosition::Lookback(Duration::from_secs(7200))),
+            ),
+            (Some("middle"), None),
+            (Some("threshold(lots)"), None),
+            (Some("lookback(2w)"), None),
+        ];
+        for (position, expected) in cases {
+            let result = log_config(|log| log.initial_position = position.map(String::from));
+            match expected {
+                Some(expected) => {
+                    assert_eq!(result.unwrap().log.tail.initial_position, expected);
+                }
+                None => assert_eq!(invalid_field(result), "log.initial_position"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_oversize_line_mode() {
+        let limit = |max_bytes, mode| Some(Some(LineLimit { max_bytes, mode }));
+        let cases = vec![
+            (None, None, Some(None)),
+            // the mode only applies along with a limit
+            (None, Some("split"), Some(None)),
+            (Some(100), None, limit(100, OversizeMode::Truncate)),
+            (Some(100), Some("Split"), limit(100, OversizeMode::Split)),
+            (
+                Some(100),
+                Some("truncate"),
+                limit(100, OversizeMode::Truncate),
+            ),
+            (Some(100), Some("wrap"), None),
+        ];
+        for (max_bytes, mode, expected) in cases {
+            let result = log_config(|log| {
+                log.max_line_bytes = max_bytes;
+                log.oversize_line_mode = mode.map(String::from);
+            });
+            match expected {
+                Some(expected) => assert_eq!(result.unwrap().log.tail.line_limit, expected),
+                None => assert_eq!(invalid_field(result), "log.oversize_line_mode"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_threads() {
+        let cases = vec![
+            (None, Some(ScanConfig::default().threads)),
+            (Some(1), Some(1)),
+            (Some(8), Some(8)),
+            (Some(0), None),
+        ];
+        for (threads, expected) in cases {
+            let result = log_config(|log| log.scan_threads = threads);
+            match expected {
+                Some(expected) => {
+                    assert_eq!(result.unwrap().log.tail.watch.scan.threads, expected);
+                }
+                None => assert_eq!(invalid_field(result), "log.scan_threads"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_framing() {
+        let cases = vec![
+            (Some("crlf"), None, Some(Framing::CrLf)),
+            (Some("length_prefixed"), None, Some(Framing::LengthPrefixed)),
+            (None, Some("||"), Some(Framing::Delimiter("||".into()))),
+            // a rule sets exactly one of the two
+            (Some("nul"), Some("||"), None),
+            (None, None, None),
+            (None, Some(""), None),
+            (Some("sideways"), None, None),
+        ];
+        for (framing, delimiter, expected) in cases {
+            let result = log_config(|log| {
+                log.framing = Some(vec![raw::FramingRule {
+                    glob: vec!["*.log".into()],
+                    regex: Vec::new(),
+                    framing: framing.map(String::from),
+                    delimiter: delimiter.map(String::from),
+                }])
+            });
+            match expected {
+                Some(expected) => {
+                    let config = result.unwrap();
+                    assert_eq!(config.log.tail.framing.len(), 1);
+                    assert_eq!(config.log.tail.framing[0].framing(), &expected);
+                }
+                None => assert_eq!(invalid_field(result), "log.framing"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_path_map() {
+        let cases = vec![
+            (vec![], Some("/host/var/log/a.log")),
+            (vec!["/host/var/log=/var/log"], Some("/var/log/a.log")),
+            (vec!["/host=/", "/host/var=/srv"], Some("/srv/log/a.log")),
+            (vec![" /host = /mnt "], Some("/mnt/var/log/a.log")),
+            (vec!["host/var/log=/var/log"], None),
+            (vec!["/host/var/log"], None),
+        ];
+        for (rules, expected) in cases {
+            let result = log_config(|log| {
+                log.path_map = Some(rules.iter().map(|rule| rule.to_string()).collect())
+            });
+            match expected {
+                Some(expected) => {
+                    let path_map = result.unwrap().log.tail.path_map;
+                    let path = PathBuf::from("/host/var/log/a.log");
+                    assert_eq!(path_map.apply(&path), PathBuf::from(expected));
+                }
+                None => assert_eq!(invalid_field(result), "log.path_map"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let rule = |glob: &str| vec![glob.to_string()];
+        type Update = Box<dyn Fn(&mut raw::LogConfig)>;
+        let cases: Vec<(&str, Update)> = vec![
+            (
+                "log.initial_position",
+                Box::new(|log| log.initial_position = Some("soon".into())),
+            ),
+            (
+                "log.oversize_line_mode",
+                Box::new(|log| {
+                    log.max_line_bytes = Some(10);
+                    log.oversize_line_mode = Some("fold".into());
+                }),
+            ),
+            (
+                "log.scan_threads",
+                Box::new(|log| log.scan_threads = Some(0)),
+            ),
+            (
+                "log.path_map",
+                Box::new(|log| log.path_map = Some(vec!["nowhere".into()])),
+            ),
+            (
+                "log.encoding",
+                Box::new(move |log| {
+                    log.encoding = Some(vec![raw::EncodingRule {
+                        glob: rule("*.log"),
+                        regex: Vec::new(),
+                        encoding: "klingon".into(),
+                    }])
+                }),
+            ),
+            (
+                "log.format",
+                Box::new(move |log| {
+                    log.format = Some(vec![raw::FormatRule {
+                        glob: rule("*.log"),
+                        regex: Vec::new(),
+                        format: "syslog".into(),
+                    }])
+                }),
+            ),
+            (
+                "log.framing",
+                Box::new(move |log| {
+                    log.framing = Some(vec![raw::FramingRule {
+                        glob: rule("*.log"),
+                        regex: Vec::new(),
+                        framing: None,
+                        delimiter: None,
+                    }])
+                }),
+            ),
+        ];
+        for (field, update) in cases {
+            assert_eq!(invalid_field(log_config(update)), field);
+        }
+    }
+
     #[test]
     fn e2e() {
         let _ = remove_file("test.yaml");
@@ -4,6 +4,7 @@ use http::types::params::Params;
 
 use crate::error::ConfigError;
 use crate::get_hostname;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
@@ -45,9 +46,55 @@ pub struct HttpConfig {
 pub struct LogConfig {
     pub dirs: Vec<PathBuf>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_position: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_dirs: Option<Vec<PathBuf>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_max_entries: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_line_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oversize_line_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_budget_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_budget_lines: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_idle_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_grace_period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dedupe_links: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proc_discovery_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_map: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub include: Option<Rules>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exclude: Option<Rules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_include: Option<Rules>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiline: Option<Vec<MultilineRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<Vec<EncodingRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<Vec<FormatRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub framing: Option<Vec<FramingRule>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_fields: Option<Vec<PathFieldRule>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
@@ -56,6 +103,59 @@ pub struct Rules {
     pub regex: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MultilineRule {
+    #[serde(default)]
+    pub glob: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct EncodingRule {
+    #[serde(default)]
+    pub glob: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    pub encoding: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct FormatRule {
+    #[serde(default)]
+    pub glob: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    pub format: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct FramingRule {
+    #[serde(default)]
+    pub glob: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub framing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delimiter: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct PathFieldRule {
+    pub regex: String,
+    // named capture -> field
+    pub fields: BTreeMap<String, String>,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -88,6 +188,23 @@ impl Default for LogConfig {
     fn default() -> Self {
         LogConfig {
             dirs: vec!["/var/log/".into()],
+            db_path: Some("/var/lib/logdna/".into()),
+            initial_position: Some("threshold(8192)".into()),
+            poll_dirs: None,
+            poll_interval: None,
+            scan_threads: None,
+            scan_max_depth: None,
+            scan_max_entries: None,
+            max_line_bytes: None,
+            oversize_line_mode: None,
+            read_budget_bytes: Some(1048576),
+            read_budget_lines: None,
+            file_idle_timeout: Some(300),
+            max_open_files: None,
+            delete_grace_period: Some(30),
+            dedupe_links: None,
+            proc_discovery_interval: None,
+            path_map: None,
             include: Some(Rules {
                 glob: vec!["*.log".parse().unwrap(), "!(*.*)".parse().unwrap()],
                 regex: Vec::new(),
@@ -109,6 +226,12 @@ impl Default for LogConfig {
                 ],
                 regex: Vec::new(),
             }),
+            binary_include: None,
+            multiline: None,
+            encoding: None,
+            format: None,
+            framing: None,
+            path_fields: None,
         }
     }
 }
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+#[cfg(test)]
+use std::{any::type_name, cell::Cell, ptr};
+
+/// A handle to a value stored in an Arena
+///
+/// Each slot counts how many times it has been reused, so a key to a removed value never resolves
+/// to whatever is stored in it's slot later on.
+pub struct Key<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(test)]
+thread_local! {
+    // the arena last read from on this thread and the name of it's value type, lets the tests
+    // written against the pointers entries used to be handed out as resolve a key on it's own
+    static LAST_READ: Cell<(*const (), &'static str)> = const { Cell::new((ptr::null(), "")) };
+}
+
+#[cfg(test)]
+impl<T> Key<T> {
+    /// Resolves the key through the arena last read from on this thread
+    ///
+    /// Panics if that arena holds another type or the value behind the key was removed.
+    ///
+    /// # Safety
+    ///
+    /// The arena must not have been dropped or moved since, nor the value mutated while the
+    /// returned reference lives.
+    pub unsafe fn as_ref<'a>(&self) -> &'a T {
+        let (arena, name) = LAST_READ.with(Cell::get);
+        assert!(
+            !arena.is_null() && name == type_name::<T>(),
+            "no arena to resolve {:?} in",
+            self
+        );
+        let arena = &*(arena as *const Arena<T>);
+        &arena[*self]
+    }
+
+    /// Like as_ref, returning a pointer instead
+    ///
+    /// # Safety
+    ///
+    /// See as_ref.
+    pub unsafe fn as_ptr(&self) -> *mut T {
+        self.as_ref() as *const T as *mut T
+    }
+}
+
+// implemented by hand as deriving would require T to implement the traits too
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Key<T> {}
+
+impl<T> PartialEq for Key<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Key<T> {}
+
+impl<T> Hash for Key<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({}v{})", self.index, self.generation)
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Stores values in a Vec, handing out generation checked keys instead of references
+#[derive(Debug)]
+pub struct Arena<T> {
+    slots: Vec<Slot<T>>,
+    // the indexes of empty slots
+    free: Vec<u32>,
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> Key<T> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some(value);
+        Key {
+            index,
+            generation: slot.generation,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Removes and returns the value behind key, None if it was already removed
+    pub fn remove(&mut self, key: Key<T>) -> Option<T> {
+        let slot = self.slots.get_mut(key.index as usize)?;
+        if slot.generation != key.generation || slot.value.is_none() {
+            return None;
+        }
+
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(key.index);
+        slot.value.take()
+    }
+
+    pub fn get(&self, key: Key<T>) -> Option<&T> {
+        #[cfg(test)]
+        LAST_READ.with(|last| last.set((self as *const Self as *const (), type_name::<T>())));
+        match self.slots.get(key.index as usize) {
+            Some(slot) if slot.generation == key.generation => slot.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: Key<T>) -> Option<&mut T> {
+        match self.slots.get_mut(key.index as usize) {
+            Some(slot) if slot.generation == key.generation => slot.value.as_mut(),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, key: Key<T>) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Iterates over the stored values, in no particular order
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().filter_map(|slot| slot.value.as_ref())
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Index<Key<T>> for Arena<T> {
+    type Output = T;
+
+    /// Panics if the value behind key was removed
+    fn index(&self, key: Key<T>) -> &T {
+        self.get(key).expect("key refers to a removed value")
+    }
+}
+
+impl<T> IndexMut<Key<T>> for Arena<T> {
+    fn index_mut(&mut self, key: Key<T>) -> &mut T {
+        self.get_mut(key).expect("key refers to a removed value")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_stale_keys() {
+        let mut arena = Arena::new();
+        let a = arena.insert("a");
+        let b = arena.insert("b");
+        assert_eq!(arena[a], "a");
+        assert_eq!(arena[b], "b");
+
+        assert_eq!(arena.remove(a), Some("a"));
+        assert_eq!(arena.remove(a), None);
+        assert!(!arena.contains(a));
+
+        // the slot is reused but the old key stays invalid
+        let c = arena.insert("c");
+        assert_ne!(a, c);
+        assert_eq!(arena.get(a), None);
+        assert_eq!(arena.get_mut(a), None);
+        assert_eq!(arena[c], "c");
+        assert_eq!(arena[b], "b");
+        assert_eq!(arena.get(b), Some(&"b"));
+    }
+}
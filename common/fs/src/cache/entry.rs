@@ -1,31 +1,32 @@
+use crate::cache::arena::Key;
+use crate::cache::handle::FileHandle;
+use crate::cache::watch::WatchDescriptor;
 use crate::cache::Children;
 use crate::rule::Rules;
-use inotify::WatchDescriptor;
 use std::ffi::OsString;
-use std::fs::File;
 use std::path::PathBuf;
-use std::ptr::NonNull;
 
-pub type EntryPtr<T> = NonNull<Entry<T>>;
+/// Identifies an entry in a FileSystem, the key of a removed entry never refers to another entry
+pub type EntryKey<T> = Key<Entry<T>>;
 
 #[derive(Debug)]
 pub enum Entry<T> {
     File {
         name: OsString,
-        parent: EntryPtr<T>,
+        parent: EntryKey<T>,
         wd: WatchDescriptor,
         data: T,
-        file_handle: File,
+        file_handle: FileHandle,
     },
     Dir {
         name: OsString,
-        parent: Option<EntryPtr<T>>,
+        parent: Option<EntryKey<T>>,
         children: Children<T>,
         wd: WatchDescriptor,
     },
     Symlink {
         name: OsString,
-        parent: EntryPtr<T>,
+        parent: EntryKey<T>,
         link: PathBuf,
         wd: WatchDescriptor,
         rules: Rules,
@@ -41,14 +42,14 @@ impl<T> Entry<T> {
         }
     }
 
-    pub fn parent(&self) -> Option<EntryPtr<T>> {
+    pub fn parent(&self) -> Option<EntryKey<T>> {
         match self {
             Entry::File { parent, .. } | Entry::Symlink { parent, .. } => Some(*parent),
             Entry::Dir { parent, .. } => *parent,
         }
     }
 
-    pub fn set_parent(&mut self, new_parent: EntryPtr<T>) {
+    pub fn set_parent(&mut self, new_parent: EntryKey<T>) {
         match self {
             Entry::File { parent, .. } | Entry::Symlink { parent, .. } => *parent = new_parent,
             Entry::Dir { parent, .. } => *parent = Some(new_parent),
@@ -70,6 +71,13 @@ impl<T> Entry<T> {
         }
     }
 
+    pub fn children(&self) -> Option<&Children<T>> {
+        match self {
+            Entry::Dir { children, .. } => Some(children),
+            _ => None,
+        }
+    }
+
     pub fn children_mut(&mut self) -> Option<&mut Children<T>> {
         match self {
             Entry::Dir { children, .. } => Some(children),
@@ -83,6 +91,14 @@ impl<T> Entry<T> {
         }
     }
 
+    pub fn set_watch_descriptor(&mut self, new_wd: WatchDescriptor) {
+        match self {
+            Entry::Dir { wd, .. } | Entry::Symlink { wd, .. } | Entry::File { wd, .. } => {
+                *wd = new_wd
+            }
+        }
+    }
+
     pub fn data_mut(&mut self) -> Option<&mut T> {
         match self {
             Entry::Dir { .. } | Entry::Symlink { .. } => None,
@@ -90,7 +106,7 @@ impl<T> Entry<T> {
         }
     }
 
-    pub fn file_handle(&self) -> Option<&File> {
+    pub fn file_handle(&self) -> Option<&FileHandle> {
         match self {
             Entry::Dir { .. } | Entry::Symlink { .. } => None,
             Entry::File { file_handle, .. } => Some(file_handle),
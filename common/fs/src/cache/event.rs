@@ -1,14 +1,14 @@
-use crate::cache::entry::EntryPtr;
+use crate::cache::entry::EntryKey;
 
 /// Represents a filesystem event
 #[derive(Debug)]
 pub enum Event<T> {
     /// A file was created initialized
-    Initialize(EntryPtr<T>),
+    Initialize(EntryKey<T>),
     /// A new file was created
-    New(EntryPtr<T>),
+    New(EntryKey<T>),
     /// A file was written too
-    Write(EntryPtr<T>),
+    Write(EntryKey<T>),
     /// A file was deleted
-    Delete(EntryPtr<T>),
+    Delete(EntryKey<T>),
 }
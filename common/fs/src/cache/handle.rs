@@ -0,0 +1,90 @@
+use crate::offsets::FileId;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// A read handle to a file that can be closed while the file is idle and reopened through it's
+/// path later on
+#[derive(Debug)]
+pub struct FileHandle {
+    file: Option<File>,
+    id: FileId,
+}
+
+impl FileHandle {
+    /// Creates a closed handle to the file at path, nothing is opened until reopen is called
+    pub fn new(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            file: None,
+            id: FileId::from_metadata(&path.metadata()?),
+        })
+    }
+
+    /// The open file, None if the handle is closed
+    pub fn get(&self) -> Option<&File> {
+        self.file.as_ref()
+    }
+
+    /// The device and inode of the file behind the handle
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Closes the file, releasing the descriptor and the disk space of a deleted file
+    pub fn close(&mut self) {
+        self.file = None;
+    }
+
+    /// Takes the open file out of the handle, leaving it closed
+    pub fn take(&mut self) -> Option<File> {
+        self.file.take()
+    }
+
+    /// Opens a closed handle through path, returning false if path now leads to a different file
+    /// than before, in which case the handle is moved over to the new file
+    pub fn reopen(&mut self, path: &Path) -> io::Result<bool> {
+        let file = OpenOptions::new().read(true).open(path)?;
+        let id = FileId::new(&file)?;
+        let same = id == self.id;
+        self.file = Some(file);
+        self.id = id;
+        Ok(same)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::rename;
+    use tempfile::TempDir;
+
+    #[test]
+    fn handle_reopen_verifies_inode() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        File::create(&path).unwrap();
+
+        let mut handle = FileHandle::new(&path).unwrap();
+        assert!(!handle.is_open());
+        assert!(handle.reopen(&path).unwrap());
+        assert!(handle.get().is_some());
+
+        handle.close();
+        assert!(handle.get().is_none());
+
+        // the path now leads to another file
+        let other = tempdir.path().join("other.log");
+        File::create(&other).unwrap();
+        rename(&other, &path).unwrap();
+        assert!(!handle.reopen(&path).unwrap());
+        assert!(handle.reopen(&path).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+        handle.close();
+        assert!(handle.reopen(&path).is_err());
+    }
+}
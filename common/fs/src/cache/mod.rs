@@ -1,48 +1,79 @@
-use crate::cache::entry::{Entry, EntryPtr};
+use crate::cache::arena::Arena;
+use crate::cache::entry::{Entry, EntryKey};
 use crate::cache::event::Event;
-use crate::cache::watch::{WatchEvent, Watcher};
+use crate::cache::handle::FileHandle;
+use crate::cache::scan::ScanConfig;
+use crate::cache::watch::{WatchConfig, WatchDescriptor, WatchEvent, Watcher};
+use crate::offsets::FileId;
+use crate::remap::PathMap;
 use crate::rule::{GlobRule, Rules, Status};
-use hashbrown::hash_map::Entry as HashMapEntry;
-use hashbrown::HashMap;
-use inotify::WatchDescriptor;
+use hashbrown::{HashMap, HashSet};
 use metrics::Metrics;
 use std::cell::RefCell;
 use std::ffi::OsString;
 use std::fmt;
 use std::fs::read_dir;
-use std::fs::OpenOptions;
 use std::iter::FromIterator;
-use std::ops::{Deref, DerefMut};
-use std::path::{Component, PathBuf};
-use std::ptr::NonNull;
+use std::ops::Index;
+use std::path::{Component, Path, PathBuf};
 use std::rc::Rc;
 
+pub mod arena;
 pub mod entry;
 pub mod event;
-mod watch;
+pub mod handle;
+pub mod scan;
+pub mod watch;
 
-type Children<T> = HashMap<OsString, Box<Entry<T>>>;
-type Symlinks<T> = HashMap<PathBuf, Vec<EntryPtr<T>>>;
-type WatchDescriptors<T> = HashMap<WatchDescriptor, Vec<EntryPtr<T>>>;
+type Children<T> = HashMap<OsString, EntryKey<T>>;
+type Symlinks<T> = HashMap<PathBuf, Vec<EntryKey<T>>>;
+type WatchDescriptors<T> = HashMap<WatchDescriptor, Vec<EntryKey<T>>>;
+
+// the longest chain of symlinks that is followed, longer chains are treated like cycles
+const MAX_LINK_DEPTH: usize = 16;
+// the most paths an entry resolves to, bounds the fan out of many symlinks leading to one place
+const MAX_PATHS_PER_ENTRY: usize = 64;
 
 pub struct FileSystem<T> {
     watcher: Watcher,
-    root: Box<Entry<T>>,
+    // every entry lives in the arena, entries refer to each other by key
+    entries: Arena<Entry<T>>,
+    root: EntryKey<T>,
 
     symlinks: Rc<RefCell<Symlinks<T>>>,
     watch_descriptors: Rc<RefCell<WatchDescriptors<T>>>,
 
     master_rules: Rules,
+    // the master rules are evaluated against the paths files are reported under
+    path_map: PathMap,
+    // the initial dirs can contain wildcards, e.g /srv/*/logs
+    initial_dirs: Vec<PathBuf>,
     initial_dir_rules: Rules,
+    // the parents of the initial dirs, only directories pass them so that directories matching a
+    // wildcard are picked up when they're created
+    initial_dir_parent_rules: Rules,
+    scan_config: ScanConfig,
 
     initial_events: Vec<Event<T>>,
 }
 
 impl<T: Default> FileSystem<T> {
     pub fn new(inital_dirs: Vec<PathBuf>, rules: Rules) -> Self {
-        let mut watcher = Watcher::new().expect("unable to initialize inotify");
+        Self::with_config(inital_dirs, rules, &WatchConfig::default(), PathMap::new())
+    }
 
-        let root = Box::new(Entry::Dir {
+    /// Creates a FileSystem that watches paths as described by config, evaluating rules against
+    /// paths as rewritten by path_map
+    pub fn with_config(
+        inital_dirs: Vec<PathBuf>,
+        rules: Rules,
+        config: &WatchConfig,
+        path_map: PathMap,
+    ) -> Self {
+        let mut watcher = Watcher::new(config).expect("unable to initialize inotify");
+
+        let mut entries = Arena::new();
+        let root = entries.insert(Entry::Dir {
             name: "/".into(),
             parent: None,
             children: Children::new(),
@@ -50,24 +81,32 @@ impl<T: Default> FileSystem<T> {
         });
 
         let mut initial_dir_rules = Rules::new();
+        let mut initial_dir_parent_rules = Rules::new();
         for path in inital_dirs.iter() {
-            append_rules(&mut initial_dir_rules, path.clone());
+            append_dir_rules(&mut initial_dir_rules, path);
+            append_parent_rules(&mut initial_dir_parent_rules, path.clone());
         }
 
         let mut fs = Self {
+            entries,
             root,
             symlinks: Rc::new(RefCell::new(Symlinks::new())),
             watch_descriptors: Rc::new(RefCell::new(WatchDescriptors::new())),
             master_rules: rules,
+            path_map,
+            initial_dirs: inital_dirs.clone(),
             initial_dir_rules,
+            initial_dir_parent_rules,
+            scan_config: config.scan.clone(),
             watcher,
             initial_events: Vec::new(),
         };
 
-        let root = EntryPtr::from(fs.root.deref_mut());
         fs.register(root);
 
         for dir in inital_dirs.iter() {
+            // the directories matching a wildcard are found by scanning the path leading up to it
+            let dir = &static_prefix(dir);
             let mut path_cpy = dir.clone();
             loop {
                 if !path_cpy.exists() {
@@ -78,7 +117,7 @@ impl<T: Default> FileSystem<T> {
                 }
             }
 
-            for path in recursive_scan(dir) {
+            for path in fs.scan(dir, true) {
                 fs.insert(&path, &mut |fs_ref, event| {
                     match event {
                         Event::New(entry) => fs_ref.initial_events.push(Event::Initialize(entry)),
@@ -110,6 +149,85 @@ impl<T: Default> FileSystem<T> {
         for event in events {
             self.process(event, &mut callback);
         }
+
+        if let Some(degraded) = self.watcher.retry_due() {
+            self.retry_degraded(degraded, &mut callback);
+        }
+    }
+
+    /// Returns the entry behind key, None if it has been removed from the cache
+    pub fn entry(&self, key: EntryKey<T>) -> Option<&Entry<T>> {
+        self.entries.get(key)
+    }
+
+    /// Returns the entry behind key, None if it has been removed from the cache
+    pub fn entry_mut(&mut self, key: EntryKey<T>) -> Option<&mut Entry<T>> {
+        self.entries.get_mut(key)
+    }
+
+    // moves paths that fell back to polling when inotify ran out of watches back to inotify
+    fn retry_degraded<F: FnMut(&mut FileSystem<T>, Event<T>)>(
+        &mut self,
+        degraded: Vec<WatchDescriptor>,
+        callback: &mut F,
+    ) {
+        for wd in degraded {
+            let entries = match self.watch_descriptors.borrow().get(&wd) {
+                Some(entries) => entries.clone(),
+                None => continue,
+            };
+            let path = self.resolve_direct_path(&self.entries[entries[0]]);
+
+            let new_wd = match self.watcher.retry(&wd, &path) {
+                Ok(Some(v)) => v,
+                // still exhausted, the remaining paths would fail too
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("unable to move {:?} back to inotify: {:?}", path, e);
+                    continue;
+                }
+            };
+
+            {
+                let mut watch_descriptors = self.watch_descriptors.borrow_mut();
+                watch_descriptors.remove(&wd);
+                for key in entries.iter().copied() {
+                    self.entries[key].set_watch_descriptor(new_wd.clone());
+                    watch_descriptors
+                        .entry(new_wd.clone())
+                        .or_default()
+                        .push(key);
+                }
+            }
+
+            // catch up on changes made between the last poll and the inotify watch
+            for key in entries {
+                match self.entries.get(key) {
+                    Some(Entry::File { .. }) => callback(self, Event::Write(key)),
+                    Some(Entry::Dir { .. }) => {
+                        let children = match read_dir(&path) {
+                            Ok(v) => v,
+                            Err(_) => continue,
+                        };
+                        for child in children.flatten() {
+                            let child = child.path();
+                            if self.lookup(&child).is_some() {
+                                continue;
+                            }
+                            if let Some(new_entry) = self.insert(&child, callback) {
+                                if let Some(Entry::Dir { .. }) = self.entries.get(new_entry) {
+                                    for new_path in self.scan(&child, false) {
+                                        self.insert(&new_path, callback);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // removed by an earlier callback
+                    Some(Entry::Symlink { .. }) | None => {}
+                }
+            }
+        }
     }
 
     // handles inotify events and may produce Event(s) that are return upstream through sender
@@ -147,7 +265,7 @@ impl<T: Default> FileSystem<T> {
                             return;
                         }
 
-                        let mut path = self.resolve_direct_path(unsafe { entries[0].as_ref() });
+                        let mut path = self.resolve_direct_path(&self.entries[entries[0]]);
                         path.push(from_name.clone());
                         path
                     }
@@ -167,7 +285,7 @@ impl<T: Default> FileSystem<T> {
                             return;
                         }
 
-                        let mut path = self.resolve_direct_path(unsafe { entries[0].as_ref() });
+                        let mut path = self.resolve_direct_path(&self.entries[entries[0]]);
                         path.push(to_name.clone());
                         path
                     }
@@ -191,10 +309,71 @@ impl<T: Default> FileSystem<T> {
                     self.process_delete(&from_wd, from_name, &mut callback);
                 }
             }
-            WatchEvent::Overflow => panic!("overflowed kernel queue!"),
+            WatchEvent::Overflow => {
+                Metrics::fs().increment_overflows();
+                warn!("overflowed kernel queue, rescanning watched directories");
+                self.process_overflow(&mut callback);
+            }
         };
     }
 
+    // events were dropped by the kernel so the cache can no longer be trusted, rescan the initial
+    // dirs and bring the cache back in line with what is actually on disk
+    fn process_overflow<F: FnMut(&mut FileSystem<T>, Event<T>)>(&mut self, callback: &mut F) {
+        // drop entries that no longer exist or whose path now refers to a different file
+        for path in self.entry_paths() {
+            let key = match self.lookup(&path) {
+                Some(v) => v,
+                None => continue, // already removed along with a parent
+            };
+
+            let replaced = match &self.entries[key] {
+                Entry::File { file_handle, .. } => match path.symlink_metadata() {
+                    Ok(on_disk) => FileId::from_metadata(&on_disk) != file_handle.id(),
+                    Err(_) => true,
+                },
+                _ => !(path.exists() || path.read_link().is_ok()),
+            };
+
+            if replaced {
+                debug!("reconciling {:?} after overflow", path);
+                self.remove(&path, callback);
+            }
+        }
+
+        // add anything that was missed, insert is a no-op for tracked paths
+        for dir in self.initial_dirs.clone() {
+            for path in self.scan(&static_prefix(&dir), true) {
+                self.insert(&path, callback);
+            }
+        }
+
+        // writes may have been dropped too, so give every file a chance to catch up
+        for path in self.entry_paths() {
+            if let Some(key) = self.lookup(&path) {
+                if let Entry::File { .. } = self.entries[key] {
+                    callback(self, Event::Write(key));
+                }
+            }
+        }
+    }
+
+    // returns the direct path of every entry in the cache, parents before children
+    fn entry_paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        let mut stack = vec![self.root];
+        while let Some(key) = stack.pop() {
+            let entry = &self.entries[key];
+            if let Entry::Dir { children, .. } = entry {
+                stack.extend(children.values().copied());
+            }
+            if entry.parent().is_some() {
+                paths.push(self.resolve_direct_path(entry));
+            }
+        }
+        paths
+    }
+
     fn process_create<F: FnMut(&mut FileSystem<T>, Event<T>)>(
         &mut self,
         watch_descriptor: &WatchDescriptor,
@@ -203,7 +382,7 @@ impl<T: Default> FileSystem<T> {
     ) {
         // directories can't be a hard link so we're guaranteed the watch descriptor maps to one
         // entry
-        let entry_ptr = match self.watch_descriptors.borrow().get(watch_descriptor) {
+        let key = match self.watch_descriptors.borrow().get(watch_descriptor) {
             Some(entries) => entries[0],
             None => {
                 error!(
@@ -214,13 +393,12 @@ impl<T: Default> FileSystem<T> {
             }
         };
 
-        let entry = unsafe { entry_ptr.as_ref() };
-        let mut path = self.resolve_direct_path(entry);
+        let mut path = self.resolve_direct_path(&self.entries[key]);
         path.push(name);
 
         if let Some(new_entry) = self.insert(&path, callback) {
-            if let Entry::Dir { .. } = unsafe { new_entry.as_ref() } {
-                for new_path in recursive_scan(&path) {
+            if let Some(Entry::Dir { .. }) = self.entries.get(new_entry) {
+                for new_path in self.scan(&path, false) {
                     self.insert(&new_path, callback);
                 }
             }
@@ -238,8 +416,8 @@ impl<T: Default> FileSystem<T> {
             .borrow()
             .get(watch_descriptor)
         {
-            for entry_ptr in entries.iter() {
-                callback(self, Event::Write(*entry_ptr));
+            for key in entries.iter() {
+                callback(self, Event::Write(*key));
             }
         } else {
             error!(
@@ -257,7 +435,7 @@ impl<T: Default> FileSystem<T> {
     ) {
         // directories can't be a hard link so we're guaranteed the watch descriptor maps to one
         // entry
-        let entry_ptr = match self.watch_descriptors.borrow().get(watch_descriptor) {
+        let key = match self.watch_descriptors.borrow().get(watch_descriptor) {
             Some(entries) => entries[0],
             None => {
                 error!(
@@ -268,8 +446,7 @@ impl<T: Default> FileSystem<T> {
             }
         };
 
-        let entry = unsafe { entry_ptr.as_ref() };
-        let mut path = self.resolve_direct_path(entry);
+        let mut path = self.resolve_direct_path(&self.entries[key]);
         path.push(name);
 
         self.remove(&path, callback);
@@ -285,7 +462,7 @@ impl<T: Default> FileSystem<T> {
     ) {
         // directories can't be a hard link so we're guaranteed the watch descriptor maps to one
         // entry
-        let from_entry_ptr = match self.watch_descriptors.borrow().get(from_watch_descriptor) {
+        let from_key = match self.watch_descriptors.borrow().get(from_watch_descriptor) {
             Some(entries) => entries[0],
             None => {
                 error!(
@@ -295,7 +472,7 @@ impl<T: Default> FileSystem<T> {
                 return;
             }
         };
-        let to_entry_ptr = match self.watch_descriptors.borrow().get(to_watch_descriptor) {
+        let to_key = match self.watch_descriptors.borrow().get(to_watch_descriptor) {
             Some(entries) => entries[0],
             None => {
                 error!(
@@ -306,28 +483,23 @@ impl<T: Default> FileSystem<T> {
             }
         };
 
-        let from_entry = unsafe { from_entry_ptr.as_ref() };
-        let to_entry = unsafe { to_entry_ptr.as_ref() };
-
-        let mut from_path = self.resolve_direct_path(from_entry);
+        let mut from_path = self.resolve_direct_path(&self.entries[from_key]);
         from_path.push(from_name);
-        let mut to_path = self.resolve_direct_path(to_entry);
+        let mut to_path = self.resolve_direct_path(&self.entries[to_key]);
         to_path.push(to_name);
 
         // the entry is expected to exist
         self.rename(&from_path, &to_path, callback).unwrap();
     }
 
-    pub fn resolve_direct_path(&self, mut entry: &Entry<T>) -> PathBuf {
-        let mut components = Vec::new();
+    pub fn resolve_direct_path(&self, entry: &Entry<T>) -> PathBuf {
+        let mut components = vec![entry.name().to_str().unwrap().to_string()];
 
-        loop {
+        let mut parent = entry.parent();
+        while let Some(key) = parent {
+            let entry = &self.entries[key];
             components.push(entry.name().to_str().unwrap().to_string());
-
-            entry = match entry.parent() {
-                Some(entry) => unsafe { &*entry.as_ptr() },
-                None => break,
-            }
+            parent = entry.parent();
         }
 
         components.reverse();
@@ -336,16 +508,26 @@ impl<T: Default> FileSystem<T> {
 
     pub fn resolve_valid_paths(&self, entry: &Entry<T>) -> Vec<PathBuf> {
         let mut paths = Vec::new();
-        self.resolve_valid_paths_helper(entry, &mut paths, Vec::new());
+        self.resolve_valid_paths_helper(entry, &mut paths, Vec::new(), &mut Vec::new());
         paths
     }
 
+    // `followed` is the chain of symlinks that led to entry
     fn resolve_valid_paths_helper(
         &self,
         entry: &Entry<T>,
         paths: &mut Vec<PathBuf>,
         mut components: Vec<String>,
+        followed: &mut Vec<EntryKey<T>>,
     ) {
+        if paths.len() >= MAX_PATHS_PER_ENTRY {
+            debug!(
+                "{:?} resolves to more than {} paths, ignoring the rest",
+                paths[0], MAX_PATHS_PER_ENTRY
+            );
+            return;
+        }
+
         let symlinks = self.symlinks.clone();
 
         let mut base_components: Vec<String> = into_components(&self.resolve_direct_path(entry))
@@ -367,9 +549,21 @@ impl<T: Default> FileSystem<T> {
             if let Some(symlinks) = symlinks.borrow().get(&current_path) {
                 // check if path has a symlink to it
                 let symlink_components = raw_components[(i + 1)..].to_vec();
-                for symlink_ptr in symlinks.iter() {
-                    let symlink = unsafe { symlink_ptr.as_ref() };
-                    self.resolve_valid_paths_helper(symlink, paths, symlink_components.clone());
+                for symlink_key in symlinks.iter() {
+                    // a symlink that is already part of the chain leads round in a cycle
+                    if followed.contains(symlink_key) || followed.len() >= MAX_LINK_DEPTH {
+                        continue;
+                    }
+
+                    let symlink = &self.entries[*symlink_key];
+                    followed.push(*symlink_key);
+                    self.resolve_valid_paths_helper(
+                        symlink,
+                        paths,
+                        symlink_components.clone(),
+                        followed,
+                    );
+                    followed.pop();
                 }
             }
         }
@@ -379,7 +573,7 @@ impl<T: Default> FileSystem<T> {
         &mut self,
         path: &PathBuf,
         callback: &mut F,
-    ) -> Option<EntryPtr<T>> {
+    ) -> Option<EntryKey<T>> {
         if !self.passes(path.to_str().unwrap()) {
             info!("ignoring {:?}", path);
             return None;
@@ -391,7 +585,7 @@ impl<T: Default> FileSystem<T> {
         }
 
         let parent = self.create_dir(&path.parent().unwrap().into())?;
-        let parent = self.follow_links(parent)?;
+        let parent = self.follow_links(parent, 0)?;
 
         // We only need the last component, the parents are already inserted.
         let component = into_components(path).pop()?;
@@ -401,91 +595,100 @@ impl<T: Default> FileSystem<T> {
             return self.create_dir(path);
         }
 
-        let children = unsafe { (&mut *parent.as_ptr()).children_mut().unwrap() };
+        if let Some(key) = self.entries[parent].children()?.get(&component) {
+            return Some(*key);
+        }
 
         // Ok = symlink, Err = real path
+        let key = match path.read_link() {
+            Ok(real) => {
+                if let Err(reason) = check_symlink(path) {
+                    warn!("skipping symlink {:?} to {:?}, {}", path, real, reason);
+                    return None;
+                }
 
-        Some(match children.entry(component.clone()) {
-            HashMapEntry::Occupied(v) => EntryPtr::from((*v.into_mut()).deref()),
-            HashMapEntry::Vacant(v) => match path.read_link() {
-                Ok(real) => {
-                    let wd = match self.watcher.watch(path) {
-                        Ok(wd) => wd,
-                        Err(e) => {
-                            error!("error watching {:?}: ", e);
-                            return None;
-                        }
-                    };
+                let wd = match self.watcher.watch(path) {
+                    Ok(wd) => wd,
+                    Err(e) => {
+                        error!("error watching {:?}: ", e);
+                        return None;
+                    }
+                };
 
-                    let symlink = Box::new(Entry::Symlink {
-                        name: component,
-                        parent,
-                        link: real.clone(),
-                        wd,
-                        rules: into_rules(real.clone()),
-                    });
+                let symlink = self.entries.insert(Entry::Symlink {
+                    name: component.clone(),
+                    parent,
+                    link: real.clone(),
+                    wd,
+                    rules: into_rules(real.clone()),
+                });
 
-                    self.register(EntryPtr::from(symlink.deref()));
+                self.register(symlink);
 
-                    if self.insert(&real, callback).is_none() {
-                        debug!(
-                            "inserting symlink {:?} which points to invalid path {:?}",
-                            path, real
-                        );
+                if self.insert(&real, callback).is_none() {
+                    debug!(
+                        "inserting symlink {:?} which points to invalid path {:?}",
+                        path, real
+                    );
+                }
+
+                callback(self, Event::New(symlink));
+                symlink
+            }
+            Err(_) => {
+                let wd = match self.watcher.watch(path) {
+                    Ok(wd) => wd,
+                    Err(e) => {
+                        error!("error watching {:?}: ", e);
+                        return None;
                     }
+                };
 
-                    callback(self, Event::New(EntryPtr::from(symlink.deref())));
-                    EntryPtr::from((*v.insert(symlink)).deref())
-                }
-                Err(_) => {
-                    let wd = match self.watcher.watch(path) {
-                        Ok(wd) => wd,
-                        Err(e) => {
-                            error!("error watching {:?}: ", e);
-                            return None;
-                        }
-                    };
+                let file = self.entries.insert(Entry::File {
+                    name: component.clone(),
+                    parent,
+                    wd,
+                    data: T::default(),
+                    file_handle: FileHandle::new(path).unwrap(),
+                });
 
-                    let file = Box::new(Entry::File {
-                        name: component,
-                        parent,
-                        wd,
-                        data: T::default(),
-                        file_handle: OpenOptions::new().read(true).open(path).unwrap(),
-                    });
+                self.register(file);
 
-                    self.register(EntryPtr::from(file.deref()));
+                callback(self, Event::New(file));
+                file
+            }
+        };
 
-                    callback(self, Event::New(EntryPtr::from(file.deref())));
-                    EntryPtr::from((*v.insert(file)).deref())
-                }
-            },
-        })
+        self.entries[parent]
+            .children_mut()
+            .expect("expected entry to be a directory")
+            .insert(component, key);
+        Some(key)
     }
 
-    fn register(&mut self, entry_ptr: EntryPtr<T>) {
-        let entry = unsafe { entry_ptr.as_ref() };
+    fn register(&mut self, key: EntryKey<T>) {
+        let entry = &self.entries[key];
         let path = self.resolve_direct_path(entry);
 
         self.watch_descriptors
             .borrow_mut()
             .entry(entry.watch_descriptor().clone())
-            .or_insert(Vec::new())
-            .push(entry_ptr);
+            .or_default()
+            .push(key);
 
         if let Entry::Symlink { link, .. } = entry {
             self.symlinks
                 .borrow_mut()
                 .entry(link.clone())
-                .or_insert(Vec::new())
-                .push(entry_ptr);
+                .or_default()
+                .push(key);
         }
 
         info!("watching {:?}", path);
     }
 
-    fn unregister(&mut self, entry_ptr: EntryPtr<T>) {
-        let entry = unsafe { entry_ptr.as_ref() };
+    fn unregister(&mut self, key: EntryKey<T>) {
+        let entry = &self.entries[key];
         let path = self.resolve_direct_path(entry);
 
         let mut watch_descriptors = self.watch_descriptors.borrow_mut();
@@ -499,7 +702,7 @@ impl<T: Default> FileSystem<T> {
             }
         };
 
-        entries.retain(|other| *other != entry_ptr);
+        entries.retain(|other| *other != key);
         if entries.is_empty() {
             watch_descriptors.remove(&wd);
             let _ = self.watcher.unwatch(wd); // TODO: Handle this error case
@@ -516,7 +719,7 @@ impl<T: Default> FileSystem<T> {
                 }
             };
 
-            entries.retain(|other| *other != entry_ptr);
+            entries.retain(|other| *other != key);
             if entries.is_empty() {
                 symlinks.remove(link);
             }
@@ -529,47 +732,56 @@ impl<T: Default> FileSystem<T> {
         &mut self,
         path: &PathBuf,
         callback: &mut F,
-    ) -> Option<Box<Entry<T>>> {
-        let mut parent = self.lookup(&path.parent()?.into())?;
+    ) -> Option<Entry<T>> {
+        let parent = self.lookup(&path.parent()?.into())?;
         let component = into_components(path).pop()?;
 
-        unsafe {
-            parent
-                .as_mut()
-                .children_mut()
-                .unwrap() // parents are always dirs
-                .remove(&component)
-                .map(|entry| {
-                    self.drop_entry(EntryPtr::from(entry.deref()), callback);
-                    entry
-                })
-        }
+        let key = self.entries[parent]
+            .children_mut()
+            .unwrap() // parents are always dirs
+            .remove(&component)?;
+        self.drop_entry(key, callback);
+        self.entries.remove(key)
     }
 
+    // unregisters an entry that was detached from it's parent and it's children, the entry is kept
+    // in the arena until the caller removes it so the Delete callbacks can still resolve it's path
     fn drop_entry<F: FnMut(&mut FileSystem<T>, Event<T>)>(
         &mut self,
-        entry_ptr: EntryPtr<T>,
+        key: EntryKey<T>,
         callback: &mut F,
     ) {
-        self.unregister(entry_ptr);
-        let entry = unsafe { entry_ptr.as_ref() };
-        match entry {
-            Entry::Dir { children, .. } => {
-                for (_, child) in children {
-                    self.drop_entry(EntryPtr::from(child.deref()), callback);
+        // a symlink dropped earlier on may have taken the entry with it
+        if !self.entries.contains(key) {
+            return;
+        }
+
+        self.unregister(key);
+        match &self.entries[key] {
+            Entry::Dir { .. } => {
+                // children are detached one at a time so the rest can be looked up while each
+                // child is dropped
+                while let Some(children) = self.entries.get_mut(key).and_then(Entry::children_mut) {
+                    let child = match children.keys().next().cloned() {
+                        Some(name) => children.remove(&name).unwrap(),
+                        None => break,
+                    };
+                    self.drop_entry(child, callback);
+                    self.entries.remove(child);
                 }
             }
-            Entry::Symlink { ref link, .. } => {
+            Entry::Symlink { link, .. } => {
+                let link = link.clone();
                 // This is a hacky way to check if there are any remaining
                 // symlinks pointing to `link`
                 if !self.passes(link.to_str().unwrap()) {
                     self.remove(&link, callback);
                 }
 
-                callback(self, Event::Delete(entry_ptr));
+                callback(self, Event::Delete(key));
             }
             Entry::File { .. } => {
-                callback(self, Event::Delete(entry_ptr));
+                callback(self, Event::Delete(key));
             }
         };
     }
@@ -582,49 +794,52 @@ impl<T: Default> FileSystem<T> {
         from: &PathBuf,
         to: &PathBuf,
         callback: &mut F,
-    ) -> Option<EntryPtr<T>> {
-        let mut new_parent = self.create_dir(&to.parent().unwrap().into()).unwrap();
-        let entry = match self.lookup(from) {
-            Some(entry) => unsafe { &mut *entry.as_ptr() },
+    ) -> Option<EntryKey<T>> {
+        let new_parent = self.create_dir(&to.parent().unwrap().into()).unwrap();
+        let key = match self.lookup(from) {
+            Some(key) => key,
             None => {
                 return self.insert(to, callback);
             }
         };
 
         let new_name = into_components(to).pop()?;
-        let old_name = entry.name().clone();
-
-        let mut entry = unsafe {
-            entry
-                .parent()
-                .expect("cannot move /")
-                .as_mut()
-                .children_mut()
-                .expect("expected entry to be a drectory")
-                .remove(&old_name)
-                .unwrap()
-        };
+        let old_name = self.entries[key].name().clone();
+        let old_parent = self.entries[key].parent().expect("cannot move /");
+
+        self.entries[old_parent]
+            .children_mut()
+            .expect("expected entry to be a drectory")
+            .remove(&old_name)
+            .unwrap();
+
+        // the entry moved over is replaced, so it's dropped like it was deleted
+        let replaced = self.entries[new_parent]
+            .children_mut()
+            .expect("expected entry to be a directory")
+            .remove(&new_name);
+        if let Some(replaced) = replaced {
+            self.drop_entry(replaced, callback);
+            self.entries.remove(replaced);
+        }
 
+        let entry = &mut self.entries[key];
         entry.set_parent(new_parent);
         entry.set_name(new_name.clone());
-        let entry_ptr = EntryPtr::from(entry.deref());
 
-        unsafe {
-            new_parent
-                .as_mut()
-                .children_mut()
-                .expect("expected entry to be a directory")
-                .insert(new_name, entry);
-        }
+        self.entries[new_parent]
+            .children_mut()
+            .expect("expected entry to be a directory")
+            .insert(new_name, key);
 
-        Some(entry_ptr)
+        Some(key)
     }
 
     // Creates all entries for a directory.
     // If one of the entries already exists, it is skipped over.
     // The returns a linked list of all entries.
-    fn create_dir(&mut self, path: &PathBuf) -> Option<EntryPtr<T>> {
-        let mut entry = EntryPtr::from(self.root.deref());
+    fn create_dir(&mut self, path: &PathBuf) -> Option<EntryKey<T>> {
+        let mut entry = self.root;
 
         let components = into_components(path);
 
@@ -637,22 +852,30 @@ impl<T: Default> FileSystem<T> {
         for (i, component) in components.iter().enumerate().skip(1) {
             let current_path = PathBuf::from_iter(&components[0..=i]);
 
-            let children = unsafe {
-                (&mut *entry.as_ptr())
-                    .children_mut()
-                    .expect("expected entry to be a directory")
-            };
-
-            let new_entry = match children.entry(component.clone()) {
-                HashMapEntry::Occupied(v) => {
-                    let entry_ref = v.into_mut().deref_mut();
-                    match entry_ref {
-                        Entry::Symlink { link, .. } => self.lookup(link)?,
-                        _ => EntryPtr::from(entry_ref),
+            let existing = self.entries[entry]
+                .children()
+                .expect("expected entry to be a directory")
+                .get(component)
+                .copied();
+
+            let new_entry = match existing {
+                Some(key) => match self.entries[key].link() {
+                    Some(link) => {
+                        let link = link.clone();
+                        self.lookup(&link)?
                     }
-                }
-                HashMapEntry::Vacant(v) => match current_path.read_link() {
+                    None => key,
+                },
+                None => match current_path.read_link() {
                     Ok(real) => {
+                        if let Err(reason) = check_symlink(&current_path) {
+                            warn!(
+                                "skipping symlink {:?} to {:?}, {}",
+                                current_path, real, reason
+                            );
+                            return None;
+                        }
+
                         let wd = match self.watcher.watch(&current_path) {
                             Ok(wd) => wd,
                             Err(e) => {
@@ -661,7 +884,7 @@ impl<T: Default> FileSystem<T> {
                             }
                         };
 
-                        let symlink = Box::new(Entry::Symlink {
+                        let symlink = self.entries.insert(Entry::Symlink {
                             name: component.clone(),
                             parent: entry,
                             link: real.clone(),
@@ -669,13 +892,25 @@ impl<T: Default> FileSystem<T> {
                             rules: into_rules(real.clone()),
                         });
 
-                        self.register(EntryPtr::from(symlink.deref()));
+                        self.register(symlink);
 
-                        v.insert(symlink);
+                        self.entries[entry]
+                            .children_mut()
+                            .expect("expected entry to be a directory")
+                            .insert(component.clone(), symlink);
                         match self.create_dir(&real) {
                             Some(v) => v,
                             None => {
-                                panic!("unable to create symlink directory for entry {:?}", &real)
+                                warn!(
+                                    "skipping symlink {:?}, unable to follow it to {:?}",
+                                    current_path, real
+                                );
+                                self.unregister(symlink);
+                                if let Some(children) = self.entries[entry].children_mut() {
+                                    children.remove(component);
+                                }
+                                self.entries.remove(symlink);
+                                return None;
                             }
                         }
                     }
@@ -688,15 +923,19 @@ impl<T: Default> FileSystem<T> {
                             }
                         };
 
-                        let dir = Box::new(Entry::Dir {
+                        let dir = self.entries.insert(Entry::Dir {
                             name: component.clone(),
                             parent: Some(entry),
                             children: HashMap::new(),
                             wd,
                         });
 
-                        self.register(NonNull::from(dir.deref()));
-                        NonNull::from((*v.insert(dir)).deref())
+                        self.register(dir);
+                        self.entries[entry]
+                            .children_mut()
+                            .expect("expected entry to be a directory")
+                            .insert(component.clone(), dir);
+                        dir
                     }
                 },
             };
@@ -707,10 +946,28 @@ impl<T: Default> FileSystem<T> {
         Some(entry)
     }
 
+    /// The rules a path must pass for it's file to be tracked
+    pub fn rules(&self) -> &Rules {
+        &self.master_rules
+    }
+
+    /// The ids of the files tracked, whether their handles are open or not
+    pub fn file_ids(&self) -> HashSet<FileId> {
+        self.entries
+            .values()
+            .filter_map(|entry| entry.file_handle().map(FileHandle::id))
+            .collect()
+    }
+
     // Returns the entry that represents the supplied path.
     // If the path is not represented and therefor has no entry then None is return.
-    pub fn lookup(&mut self, path: &PathBuf) -> Option<EntryPtr<T>> {
-        let mut parent = EntryPtr::from(self.root.deref());
+    pub fn lookup(&self, path: &PathBuf) -> Option<EntryKey<T>> {
+        self.lookup_at_depth(path, 0)
+    }
+
+    // `depth` is the number of symlinks already followed to get to path
+    fn lookup_at_depth(&self, path: &PathBuf, depth: usize) -> Option<EntryKey<T>> {
+        let mut parent = self.root;
         let mut components = into_components(path);
         // remove the first component because it will always be the root
         components.remove(0);
@@ -723,44 +980,44 @@ impl<T: Default> FileSystem<T> {
         let last_component = components.pop()?;
 
         for component in components {
-            parent = unsafe {
-                self.follow_links(parent)?
-                    .as_mut()
-                    .children_mut()
-                    .expect("expected directory entry")
-                    .get_mut(&component)
-                    .map(|entry| EntryPtr::from((*entry).deref()))
-                    .and_then(|entry| self.follow_links(entry))?
-            };
-        }
-
-        unsafe {
-            parent
-                .as_mut()
-                .children_mut()
+            let dir = self.follow_links(parent, depth)?;
+            let child = self.entries[dir]
+                .children()
                 .expect("expected directory entry")
-                .get_mut(&last_component)
-                .map(|entry| EntryPtr::from((*entry).deref()))
+                .get(&component)
+                .copied()?;
+            parent = self.follow_links(child, depth)?;
         }
+
+        self.entries[parent]
+            .children()
+            .expect("expected directory entry")
+            .get(&last_component)
+            .copied()
     }
 
-    fn follow_links(&mut self, mut entry: EntryPtr<T>) -> Option<EntryPtr<T>> {
-        unsafe {
-            while let Some(link) = entry.as_mut().link() {
-                entry = self.lookup(link)?;
+    // returns the entry at the end of a chain of symlinks, None if the chain is broken or longer
+    // than MAX_LINK_DEPTH
+    fn follow_links(&self, mut entry: EntryKey<T>, mut depth: usize) -> Option<EntryKey<T>> {
+        while let Some(link) = self.entries[entry].link() {
+            depth += 1;
+            if depth > MAX_LINK_DEPTH {
+                debug!("not following {:?}, too many levels of symlinks", link);
+                return None;
             }
-            Some(entry)
+            entry = self.lookup_at_depth(link, depth)?;
         }
+        Some(entry)
     }
 
     fn is_symlink_target(&self, path: &str) -> bool {
-        for (_, symlink_ptrs) in self.symlinks.borrow().iter() {
-            for symlink_ptr in symlink_ptrs.iter() {
-                let symlink = unsafe { (*symlink_ptr).as_ref() };
-                match symlink {
+        for (_, symlink_keys) in self.symlinks.borrow().iter() {
+            for symlink_key in symlink_keys.iter() {
+                match &self.entries[*symlink_key] {
                     Entry::Symlink { rules, .. } => {
                         if let Status::Ok = rules.passes(path) {
-                            if let Status::Ok = self.master_rules.included(path) {
+                            let reported = self.path_map.apply_str(path);
+                            if let Status::Ok = self.master_rules.included(&*reported) {
                                 return true;
                             }
                         }
@@ -778,9 +1035,40 @@ impl<T: Default> FileSystem<T> {
         false
     }
 
+    // returns dir and the paths under it, directories matching an exclusion rule aren't descended
+    // into, the directories created while running are small so they are scanned on this thread
+    fn scan(&self, dir: &Path, parallel: bool) -> Vec<PathBuf> {
+        let mut config = self.scan_config.clone();
+        if !parallel {
+            config.threads = 1;
+        }
+        scan::scan(dir, &config, |path| match path.to_str() {
+            Some(path) => {
+                let reported = self.path_map.apply_str(path);
+                self.master_rules.excluded(&*reported) == Status::Excluded
+                    || !(self.initial_dir_rules.passes(path).is_ok()
+                        || self.initial_dir_parent_rules.passes(path).is_ok())
+            }
+            None => false,
+        })
+    }
+
+    // whether path is a directory, or was one before it was moved or deleted
+    fn is_dir(&self, path: &str) -> bool {
+        let path = PathBuf::from(path);
+        path.is_dir()
+            || matches!(
+                self.lookup(&path).and_then(|key| self.entries.get(key)),
+                Some(Entry::Dir { .. })
+            )
+    }
+
     fn is_initial_dir_target(&self, path: &str) -> bool {
-        if let Status::Ok = self.initial_dir_rules.passes(path) {
-            if let Status::Ok = self.master_rules.passes(path) {
+        let initial = self.initial_dir_rules.passes(path).is_ok()
+            || (self.initial_dir_parent_rules.passes(path).is_ok() && self.is_dir(path));
+        if initial {
+            let reported = self.path_map.apply_str(path);
+            if let Status::Ok = self.master_rules.passes(&*reported) {
                 return true;
             }
         }
@@ -794,55 +1082,68 @@ impl<T: Default> FileSystem<T> {
     }
 }
 
+impl<T> Index<EntryKey<T>> for FileSystem<T> {
+    type Output = Entry<T>;
+
+    /// Panics if the entry behind key has been removed from the cache
+    fn index(&self, key: EntryKey<T>) -> &Entry<T> {
+        &self.entries[key]
+    }
+}
+
 // conditionally implement std::fmt::Debug if the underlying type T implements it
 impl<T: fmt::Debug> fmt::Debug for FileSystem<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut builder = f.debug_struct("FileSystem");
         builder.field("root", &&self.root);
+        builder.field("entries", &&self.entries);
         builder.field("symlinks", &&self.symlinks);
         builder.field("watch_descriptors", &&self.watch_descriptors);
         builder.field("master_rules", &&self.master_rules);
+        builder.field("path_map", &&self.path_map);
         builder.field("initial_dir_rules", &&self.initial_dir_rules);
+        builder.field("initial_dir_parent_rules", &&self.initial_dir_parent_rules);
         builder.field("initial_events", &&self.initial_events);
         builder.finish()
     }
 }
 
-// recursively scans a directory for unlimited depth
-fn recursive_scan(path: &PathBuf) -> Vec<PathBuf> {
-    if !path.is_dir() {
-        return vec![];
+// returns why the symlink at path can't be followed safely, i.e it's part of a cycle, it leads to
+// one of it's own parent directories or the chain of links is too long
+fn check_symlink(path: &Path) -> Result<(), String> {
+    let mut followed = vec![path.to_path_buf()];
+    let mut current = path.to_path_buf();
+    while let Ok(target) = current.read_link() {
+        // relative targets are relative to the directory the link is in
+        current = match current.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+        if followed.contains(&current) {
+            return Err("it is part of a cycle".into());
+        }
+        if followed.len() > MAX_LINK_DEPTH {
+            return Err(format!("it is more than {} links deep", MAX_LINK_DEPTH));
+        }
+        followed.push(current.clone());
     }
 
-    let mut paths = vec![path.clone()];
-
-    // read all files/dirs in path at depth 1
-    let tmp_paths = match read_dir(&path) {
+    let real = match current.canonicalize() {
         Ok(v) => v,
-        Err(e) => {
-            error!("failed accessing {:?}: {:?}", path, e);
-            return paths;
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+            return Err("it is part of a cycle".into());
         }
+        // a dangling symlink is fine, it's target can still show up later
+        Err(_) => return Ok(()),
     };
-    // iterate over all the paths and call recursive_scan on all dirs
-    for tmp_path in tmp_paths {
-        let path = match tmp_path {
-            Ok(path) => path.path(),
-            Err(e) => {
-                error!("failed scanning directory {:?}: {:?}", path, e);
-                continue;
-            }
-        };
-        // if the path is a dir then recursively scan it also
-        // so that we have an unlimited depth scan
-        if path.is_dir() {
-            paths.append(&mut recursive_scan(&path))
-        } else {
-            paths.push(path)
-        }
+    let location = match (path.parent().map(Path::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => return Ok(()),
+    };
+    if location.starts_with(&real) {
+        return Err(format!("it leads to it's own parent directory {:?}", real));
     }
-
-    paths
+    Ok(())
 }
 
 // Split the path into it's components.
@@ -864,23 +1165,47 @@ fn into_rules(path: PathBuf) -> Rules {
 }
 
 // Attach rules for all sub paths for a path
-fn append_rules(rules: &mut Rules, mut path: PathBuf) {
+fn append_rules(rules: &mut Rules, path: PathBuf) {
+    append_dir_rules(rules, &path);
+    append_parent_rules(rules, path);
+}
+
+// Attach rules for a path and everything under it e.g. /var/log => include [/var/log, /var/log/**]
+fn append_dir_rules(rules: &mut Rules, path: &Path) {
     rules.add_inclusion(
         GlobRule::new(path.join(r"**").to_str().expect("invalid unicode in path"))
             .expect("invalid glob rule format"),
     );
+    rules.add_inclusion(
+        GlobRule::new(path.to_str().expect("invalid unicode in path"))
+            .expect("invalid glob rule format"),
+    );
+}
 
-    loop {
+// Attach rules for the parents of a path e.g. /var/log/containers => include [/, /var, /var/log]
+fn append_parent_rules(rules: &mut Rules, mut path: PathBuf) {
+    while path.pop() {
         rules.add_inclusion(
             GlobRule::new(path.to_str().expect("invalid unicode in path"))
                 .expect("invalid glob rule format"),
         );
-        if !path.pop() {
-            break;
-        }
     }
 }
 
+// Returns the leading components of a path up to the first one containing a wildcard, e.g.
+// /srv/*/logs => /srv
+fn static_prefix(path: &Path) -> PathBuf {
+    path.components()
+        .take_while(|c| !is_pattern(&c.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+// whether a path component is a glob pattern rather than a name
+fn is_pattern(component: &str) -> bool {
+    component.contains(&['*', '?', '['][..])
+        || ["!(", "@(", "+("].iter().any(|p| component.contains(p))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1077,6 +1402,119 @@ mod tests {
         });
     }
 
+    // Skips symlinks that lead round in a cycle
+    #[test]
+    fn filesystem_create_symlink_cycle() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let dir = path.join("dir");
+            let parent_link = dir.join("parent");
+            let file_path = dir.join("file.log");
+            let a = path.join("a.log");
+            let b = path.join("b.log");
+            create_dir(&dir).unwrap();
+            File::create(&file_path).unwrap();
+            symlink(&path, &parent_link).unwrap();
+            symlink(&b, &a).unwrap();
+            symlink(&a, &b).unwrap();
+
+            let mut fs = new_fs::<()>(path.clone(), None);
+
+            assert!(fs.lookup(&dir).is_some());
+            assert!(fs.lookup(&parent_link).is_none());
+            assert!(fs.lookup(&a).is_none());
+            assert!(fs.lookup(&b).is_none());
+
+            let entry = fs.lookup(&file_path).unwrap();
+            assert_eq!(fs.resolve_valid_paths(&fs[entry]), vec![file_path.clone()]);
+
+            // cycles created while running are skipped too
+            let other_link = dir.join("other");
+            symlink(&dir, &other_link).unwrap();
+            fs.read_events(&mut |_, _| {});
+            assert!(fs.lookup(&other_link).is_none());
+            assert!(fs.lookup(&file_path).is_some());
+        });
+    }
+
+    // Skips symlinks at the start of chains longer than the max link depth
+    #[test]
+    fn filesystem_create_symlink_depth() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let file_path = path.join("file.log");
+            File::create(&file_path).unwrap();
+            let mut links = vec![file_path.clone()];
+            for i in 0..=MAX_LINK_DEPTH {
+                let link = path.join(format!("link{}.log", i));
+                symlink(links.last().unwrap(), &link).unwrap();
+                links.push(link);
+            }
+
+            let fs = new_fs::<()>(path, None);
+
+            assert!(fs.lookup(&links[MAX_LINK_DEPTH]).is_some());
+            assert!(fs.lookup(&links[MAX_LINK_DEPTH + 1]).is_none());
+        });
+    }
+
+    // Skips directory symlinks whose target is reached through a chain of links that is too long
+    #[test]
+    fn filesystem_create_symlink_dir_through_long_chain() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let outside = TempDir::new().unwrap();
+
+            let target = outside.path().join("target");
+            create_dir(&target).unwrap();
+            create_dir(target.join("sub")).unwrap();
+            File::create(target.join("sub/file.log")).unwrap();
+            let mut links = vec![target];
+            for i in 0..=MAX_LINK_DEPTH {
+                let link = outside.path().join(format!("link{}", i));
+                symlink(links.last().unwrap(), &link).unwrap();
+                links.push(link);
+            }
+            let dir_link = path.join("dir");
+            symlink(links.last().unwrap().join("sub"), &dir_link).unwrap();
+            let file_path = path.join("file.log");
+            File::create(&file_path).unwrap();
+
+            let fs = new_fs::<()>(path, None);
+
+            assert!(fs.lookup(&dir_link).is_none());
+            assert!(fs.lookup(&dir_link.join("file.log")).is_none());
+            assert!(fs.lookup(&file_path).is_some());
+        });
+    }
+
+    // Caps the number of paths an entry resolves to
+    #[test]
+    fn filesystem_create_symlink_fan_out() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let file_path = path.join("file.log");
+            File::create(&file_path).unwrap();
+            for i in 0..MAX_PATHS_PER_ENTRY {
+                symlink(&file_path, path.join(format!("link{}.log", i))).unwrap();
+            }
+
+            let fs = new_fs::<()>(path, None);
+
+            let entry = fs.lookup(&file_path).unwrap();
+            let paths = fs.resolve_valid_paths(&fs[entry]);
+            assert_eq!(paths.len(), MAX_PATHS_PER_ENTRY);
+            assert_eq!(paths[0], file_path);
+        });
+    }
+
     // Creates a hardlink
     #[test]
     fn filesystem_create_hardlink() {
@@ -1568,6 +2006,176 @@ mod tests {
         });
     }
 
+    // Recovers from a dropped kernel queue by rescanning
+    #[test]
+    fn filesystem_overflow_rescan() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let a = path.join("a");
+            let b = path.join("b");
+            File::create(&a).unwrap();
+
+            let mut fs = new_fs::<()>(path, None);
+            assert!(fs.lookup(&a).is_some());
+
+            remove_file(&a).unwrap();
+            File::create(&b).unwrap();
+
+            let mut writes = 0;
+            fs.process(WatchEvent::Overflow, &mut |_, event| {
+                if let Event::Write(_) = event {
+                    writes += 1;
+                }
+            });
+
+            assert!(fs.lookup(&a).is_none());
+            assert!(fs.lookup(&b).is_some());
+            assert_eq!(writes, 1);
+        });
+    }
+
+    // Times building the cache for, and looking up every file of, a tree of 100k files, run with
+    // cargo test --release -p fs filesystem_bench -- --ignored --nocapture
+    //
+    // Over five runs each the pointer linked cache took 1.22-1.65s to build and 56-110ms for the
+    // lookups, the arena 1.14-1.64s and 46-73ms, so the two are on par within the noise.
+    #[test]
+    #[ignore]
+    fn filesystem_bench_100k_files() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            let mut files = Vec::new();
+            for i in 0..100 {
+                let dir = path.join(format!("dir{}", i));
+                create_dir(&dir).unwrap();
+                for j in 0..1000 {
+                    let file = dir.join(format!("{}.log", j));
+                    File::create(&file).unwrap();
+                    files.push(file);
+                }
+            }
+
+            let start = std::time::Instant::now();
+            let fs = new_fs::<()>(path, None);
+            let built = start.elapsed();
+            let start = std::time::Instant::now();
+            for file in files.iter() {
+                assert!(fs.lookup(file).is_some());
+            }
+            println!(
+                "built the cache in {:?}, looked up {} files in {:?}",
+                built,
+                files.len(),
+                start.elapsed()
+            );
+        });
+    }
+
+    // Doesn't descend into excluded directories when scanning
+    #[test]
+    fn filesystem_scan_prunes_excluded_dirs() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            create_dir(path.join("skip")).unwrap();
+            create_dir(path.join("keep")).unwrap();
+            File::create(path.join("skip/a.log")).unwrap();
+            File::create(path.join("keep/b.log")).unwrap();
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            rules.add_exclusion(GlobRule::new(path.join("skip").to_str().unwrap()).unwrap());
+            let fs = new_fs::<()>(path.clone(), Some(rules));
+
+            assert!(fs.lookup(&path.join("skip")).is_none());
+            assert!(fs.lookup(&path.join("skip/a.log")).is_none());
+            assert!(fs.lookup(&path.join("keep/b.log")).is_some());
+        });
+    }
+
+    // Watches the directories matching wildcards in the initial dirs, including new ones
+    #[test]
+    fn filesystem_wildcard_initial_dirs() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+            std::fs::create_dir_all(path.join("srv/a/logs")).unwrap();
+            std::fs::create_dir_all(path.join("data/x/y/log")).unwrap();
+            let a = path.join("srv/a/logs/a.log");
+            let outside = path.join("srv/a/outside.log");
+            let y = path.join("data/x/y/log/y.log");
+            for file in [&a, &outside, &y].iter() {
+                File::create(file).unwrap();
+            }
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            let dirs = vec![path.join("srv/*/logs"), path.join("data/**/log")];
+            let mut fs = FileSystem::<()>::new(dirs, rules);
+
+            assert!(fs.lookup(&a).is_some());
+            assert!(fs.lookup(&y).is_some());
+            assert!(fs.lookup(&outside).is_none());
+
+            // a matching directory created while running
+            std::fs::create_dir_all(path.join("srv/b/logs")).unwrap();
+            let b = path.join("srv/b/logs/b.log");
+            File::create(&b).unwrap();
+            fs.read_events(&mut |_, _| {});
+            assert!(fs.lookup(&b).is_some());
+
+            let late = path.join("srv/b/logs/late.log");
+            File::create(&late).unwrap();
+            fs.read_events(&mut |_, _| {});
+            assert!(fs.lookup(&late).is_some());
+        });
+    }
+
+    // Picks up changes in a polled directory without inotify events
+    #[test]
+    fn filesystem_poll_dir() {
+        run_test(|| {
+            let tempdir = TempDir::new().unwrap();
+            let path = tempdir.path().to_path_buf();
+
+            let mut rules = Rules::new();
+            rules.add_inclusion(GlobRule::new(r"**").unwrap());
+            let config = WatchConfig {
+                poll_dirs: vec![path.clone()],
+                poll_interval: std::time::Duration::from_millis(0),
+                ..Default::default()
+            };
+            let mut fs =
+                FileSystem::<()>::with_config(vec![path.clone()], rules, &config, PathMap::new());
+
+            let a = path.join("a.log");
+            File::create(&a).unwrap();
+            fs.read_events(&mut |_, _| {});
+            let entry = fs.lookup(&a);
+            assert!(entry.is_some());
+            match &fs[entry.unwrap()] {
+                Entry::File { wd, .. } => assert!(matches!(wd, WatchDescriptor::Poll(_))),
+                _ => panic!("wrong entry type"),
+            }
+
+            let mut writes = 0;
+            std::fs::write(&a, "line\n").unwrap();
+            fs.read_events(&mut |_, event| {
+                if let Event::Write(_) = event {
+                    writes += 1;
+                }
+            });
+            assert_eq!(writes, 1);
+
+            remove_file(&a).unwrap();
+            fs.read_events(&mut |_, _| {});
+            assert!(fs.lookup(&a).is_none());
+        });
+    }
+
     #[test]
     fn filesystem_resolve_valid_paths() {
         run_test(|| {
@@ -1590,7 +2198,7 @@ mod tests {
             symlink(&remote_symlink_path, &nested_symlink_path).unwrap();
             symlink(&nested_symlink_path, &double_nested_symlink_path).unwrap();
 
-            let mut fs = new_fs::<()>(path, None);
+            let fs = new_fs::<()>(path, None);
 
             let entry = unsafe { &*(fs.lookup(&file_path).unwrap()).as_ptr() };
             let resolved_paths = fs.resolve_valid_paths(entry);
@@ -0,0 +1,305 @@
+use crate::offsets::FileId;
+use hashbrown::HashSet;
+use metrics::Metrics;
+use std::collections::VecDeque;
+use std::fs::{self, read_dir};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DEFAULT_THREADS: usize = 4;
+
+/// Settings for scanning directories for the paths under them
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanConfig {
+    /// How many directories are listed at once when the watched directories are first scanned,
+    /// directories are listed one at a time on the calling thread if 1
+    pub threads: usize,
+    /// How many directories deep below a scanned directory paths are found, unbounded if None
+    pub max_depth: Option<usize>,
+    /// The most paths a scan finds, the paths past it are left out, unbounded if None
+    pub max_entries: Option<usize>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            threads: DEFAULT_THREADS,
+            max_depth: None,
+            max_entries: None,
+        }
+    }
+}
+
+// a directory to list along with how deep below the scanned directory it is
+type Job = (PathBuf, usize);
+
+// the entries of a listed directory, the entries that are directories carry their id
+struct Listing {
+    dir: PathBuf,
+    depth: usize,
+    entries: io::Result<Vec<(PathBuf, Option<FileId>)>>,
+}
+
+/// Returns dir and the paths under it, parents before their children, without descending into
+/// the directories prune returns true for, which are left out themselves as well
+///
+/// The directories are walked breadth first without recursion, so deep trees can't overflow the
+/// stack. A directory reachable through several paths, e.g through a symlink leading to one of it's
+/// parents, is only descended into once.
+pub fn scan<F: FnMut(&Path) -> bool>(dir: &Path, config: &ScanConfig, prune: F) -> Vec<PathBuf> {
+    let id = match fs::metadata(dir) {
+        Ok(metadata) if metadata.is_dir() => FileId::from_metadata(&metadata),
+        _ => return Vec::new(),
+    };
+
+    let mut walk = Walk {
+        config,
+        prune,
+        scanned: HashSet::new(),
+        paths: vec![dir.to_path_buf()],
+        full: false,
+    };
+    walk.scanned.insert(id);
+    if walk.descends(0) {
+        let root = (dir.to_path_buf(), 0);
+        if config.threads > 1 {
+            scan_parallel(root, &mut walk);
+        } else {
+            scan_inline(root, &mut walk);
+        }
+    }
+    Metrics::fs().set_pending_scan_dirs(0);
+
+    debug!("scanned {:?}, found {} paths", dir, walk.paths.len());
+    walk.paths
+}
+
+// the state of a scan, only touched by the thread the scan was started on
+struct Walk<'a, F> {
+    config: &'a ScanConfig,
+    prune: F,
+    // the directories descended into
+    scanned: HashSet<FileId>,
+    paths: Vec<PathBuf>,
+    // max_entries was reached
+    full: bool,
+}
+
+impl<'a, F: FnMut(&Path) -> bool> Walk<'a, F> {
+    // whether the entries of a directory depth levels below the scanned directory are wanted
+    fn descends(&self, depth: usize) -> bool {
+        match self.config.max_depth {
+            Some(max) => depth < max,
+            None => true,
+        }
+    }
+
+    // adds the entries of a listed directory to the found paths, returning the directories under
+    // it that are listed next
+    fn visit(&mut self, listing: Listing) -> Vec<Job> {
+        Metrics::fs().increment_scanned_dirs();
+        let entries = match listing.entries {
+            Ok(v) => v,
+            Err(e) => {
+                error!("failed accessing {:?}: {:?}", listing.dir, e);
+                return Vec::new();
+            }
+        };
+        Metrics::fs().add_scanned_entries(entries.len() as u64);
+
+        let depth = listing.depth + 1;
+        let mut dirs = Vec::new();
+        for (path, id) in entries {
+            if self.config.max_entries == Some(self.paths.len()) {
+                warn!(
+                    "stopped scanning after finding {} paths, the rest of {:?} isn't watched",
+                    self.paths.len(),
+                    listing.dir
+                );
+                self.full = true;
+                break;
+            }
+            if let Some(id) = id {
+                if (self.prune)(&path) {
+                    debug!("not scanning excluded directory {:?}", path);
+                    continue;
+                }
+                if self.descends(depth) && self.scanned.insert(id) {
+                    dirs.push((path.clone(), depth));
+                }
+            }
+            self.paths.push(path);
+        }
+        dirs
+    }
+}
+
+// lists the directories one at a time on this thread
+fn scan_inline<F: FnMut(&Path) -> bool>(root: Job, walk: &mut Walk<'_, F>) {
+    let mut queue = VecDeque::new();
+    queue.push_back(root);
+    while let Some((dir, depth)) = queue.pop_front() {
+        queue.extend(walk.visit(list(dir, depth)));
+        if walk.full {
+            break;
+        }
+    }
+}
+
+// lists the directories on a pool of threads, while the listings are walked on this thread
+fn scan_parallel<F: FnMut(&Path) -> bool>(root: Job, walk: &mut Walk<'_, F>) {
+    let (job_sender, jobs) = channel();
+    let (listing_sender, listings) = channel();
+    let jobs = Arc::new(Mutex::new(jobs));
+    let workers: Vec<_> = (0..walk.config.threads)
+        .map(|_| {
+            let jobs = jobs.clone();
+            let listings = listing_sender.clone();
+            thread::spawn(move || list_jobs(&jobs, &listings))
+        })
+        .collect();
+    drop(listing_sender);
+
+    let mut pending = 0;
+    if job_sender.send(root).is_ok() {
+        pending += 1;
+    }
+    while pending > 0 && !walk.full {
+        // every worker is gone if this fails, which only happens if they panicked
+        let listing = match listings.recv() {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+        pending -= 1;
+        for job in walk.visit(listing) {
+            if job_sender.send(job).is_ok() {
+                pending += 1;
+            }
+        }
+        Metrics::fs().set_pending_scan_dirs(pending as u64);
+    }
+
+    // the workers stop once the jobs run out, or on their next listing if the scan stopped early
+    drop(job_sender);
+    drop(listings);
+    for worker in workers {
+        if worker.join().is_err() {
+            error!("a directory scanning thread panicked");
+        }
+    }
+}
+
+// lists directories until there are no jobs left or nothing is waiting for the listings
+fn list_jobs(jobs: &Mutex<Receiver<Job>>, listings: &Sender<Listing>) {
+    loop {
+        let job = match jobs.lock() {
+            Ok(jobs) => jobs.recv(),
+            Err(_) => return,
+        };
+        let (dir, depth) = match job {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        if listings.send(list(dir, depth)).is_err() {
+            return;
+        }
+    }
+}
+
+fn list(dir: PathBuf, depth: usize) -> Listing {
+    let entries = read_dir(&dir).map(|entries| {
+        entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => {
+                    let path = entry.path();
+                    // symlinks to directories are descended into as well
+                    let id = match fs::metadata(&path) {
+                        Ok(metadata) if metadata.is_dir() => Some(FileId::from_metadata(&metadata)),
+                        _ => None,
+                    };
+                    Some((path, id))
+                }
+                Err(e) => {
+                    error!("failed scanning directory {:?}: {:?}", dir, e);
+                    None
+                }
+            })
+            .collect()
+    });
+    Listing {
+        dir,
+        depth,
+        entries,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, File};
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    fn scan_sorted(dir: &Path, config: &ScanConfig) -> Vec<PathBuf> {
+        let mut paths = scan(dir, config, |path| path.ends_with("excluded"));
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn scan_limits() {
+        let tempdir = TempDir::new().unwrap();
+        let root = tempdir.path();
+        create_dir_all(root.join("a/b/c")).unwrap();
+        create_dir_all(root.join("excluded/d")).unwrap();
+        File::create(root.join("a/1.log")).unwrap();
+        File::create(root.join("a/b/c/2.log")).unwrap();
+        File::create(root.join("excluded/d/3.log")).unwrap();
+        // a cycle back to the root is only followed once
+        symlink(root, root.join("a/b/loop")).unwrap();
+
+        let everything = vec![
+            root.to_path_buf(),
+            root.join("a"),
+            root.join("a/1.log"),
+            root.join("a/b"),
+            root.join("a/b/c"),
+            root.join("a/b/c/2.log"),
+            root.join("a/b/loop"),
+        ];
+        for threads in [1, 4].iter() {
+            let config = ScanConfig {
+                threads: *threads,
+                ..Default::default()
+            };
+            assert_eq!(scan_sorted(root, &config), everything);
+        }
+
+        let config = ScanConfig {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            scan_sorted(root, &config),
+            vec![
+                root.to_path_buf(),
+                root.join("a"),
+                root.join("a/1.log"),
+                root.join("a/b"),
+            ]
+        );
+
+        let config = ScanConfig {
+            max_entries: Some(3),
+            ..Default::default()
+        };
+        let paths = scan(root, &config, |_| false);
+        assert_eq!(paths.len(), 3);
+        assert_eq!(paths[0], root);
+
+        assert!(scan(&root.join("a/1.log"), &ScanConfig::default(), |_| false).is_empty());
+    }
+}
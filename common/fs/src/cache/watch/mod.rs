@@ -0,0 +1,227 @@
+use crate::cache::scan::ScanConfig;
+use crate::cache::watch::notify::InotifyBackend;
+use crate::cache::watch::poll::PollBackend;
+use hashbrown::HashSet;
+use metrics::Metrics;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+mod notify;
+mod poll;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// how often paths polled because inotify ran out of watches are moved back to inotify
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Identifies a watched path, tagged with the backend that watches it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchDescriptor {
+    Inotify(inotify::WatchDescriptor),
+    Poll(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    Create {
+        wd: WatchDescriptor,
+        name: OsString,
+    },
+    Modify {
+        wd: WatchDescriptor,
+    },
+    Delete {
+        wd: WatchDescriptor,
+        name: OsString,
+    },
+    Move {
+        from_wd: WatchDescriptor,
+        from_name: OsString,
+        to_wd: WatchDescriptor,
+        to_name: OsString,
+    },
+    MovedFrom {
+        wd: WatchDescriptor,
+        name: OsString,
+        cookie: u32,
+    },
+    MovedTo {
+        wd: WatchDescriptor,
+        name: OsString,
+        cookie: u32,
+    },
+    Overflow,
+}
+
+/// A source of WatchEvents for the paths it's asked to watch
+pub trait Backend {
+    /// Starts watching a path, watching the same file twice returns the same descriptor
+    fn watch(&mut self, path: &Path) -> io::Result<WatchDescriptor>;
+    /// Stops watching the path behind a descriptor
+    fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()>;
+    /// Returns the events that happened since the last call without blocking
+    fn read_events(&mut self, buffer: &mut [u8]) -> io::Result<Vec<WatchEvent>>;
+}
+
+/// Settings for how paths are watched
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    /// Directories that are watched by polling instead of inotify, e.g network mounts where
+    /// writes from other hosts don't generate inotify events
+    pub poll_dirs: Vec<PathBuf>,
+    /// How often polled directories are checked for changes
+    pub poll_interval: Duration,
+    /// How the watched directories are scanned for the paths under them
+    pub scan: ScanConfig,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            poll_dirs: Vec::new(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            scan: ScanConfig::default(),
+        }
+    }
+}
+
+/// Watches paths with inotify, or by polling for the paths inside the configured poll dirs
+///
+/// Paths that can't be watched with inotify because the watch limit (fs.inotify.max_user_watches)
+/// is exhausted are polled instead until a retry finds capacity again.
+pub struct Watcher {
+    inotify: InotifyBackend,
+    poll: PollBackend,
+    poll_dirs: Vec<PathBuf>,
+    // polled watches that should be inotify watches
+    degraded: HashSet<WatchDescriptor>,
+    last_retry: Instant,
+}
+
+impl Watcher {
+    pub fn new(config: &WatchConfig) -> io::Result<Self> {
+        Ok(Self {
+            inotify: InotifyBackend::new()?,
+            poll: PollBackend::new(config.poll_interval),
+            poll_dirs: config.poll_dirs.clone(),
+            degraded: HashSet::new(),
+            last_retry: Instant::now(),
+        })
+    }
+
+    pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<WatchDescriptor> {
+        let path = path.as_ref();
+        if self.poll_dirs.iter().any(|dir| path.starts_with(dir)) {
+            return self.poll.watch(path);
+        }
+
+        match self.inotify.watch(path) {
+            Err(ref e) if is_exhausted(e) => {
+                let wd = self.poll.watch(path)?;
+                if self.degraded.insert(wd.clone()) {
+                    Metrics::fs().set_degraded_watches(self.degraded.len() as u64);
+                    warn!(
+                        "inotify watch limit reached, polling {:?} instead ({} path(s) degraded)",
+                        path,
+                        self.degraded.len()
+                    );
+                }
+                Ok(wd)
+            }
+            result => result,
+        }
+    }
+
+    /// Returns the degraded watches if it's time to retry moving them back to inotify
+    pub fn retry_due(&mut self) -> Option<Vec<WatchDescriptor>> {
+        if self.degraded.is_empty() || self.last_retry.elapsed() < RETRY_INTERVAL {
+            return None;
+        }
+
+        self.last_retry = Instant::now();
+        Some(self.degraded.iter().cloned().collect())
+    }
+
+    /// Tries to move a degraded watch of path back to inotify, returning the new descriptor or None
+    /// if the watch limit is still exhausted
+    pub fn retry(
+        &mut self,
+        wd: &WatchDescriptor,
+        path: &Path,
+    ) -> io::Result<Option<WatchDescriptor>> {
+        let new_wd = match self.inotify.watch(path) {
+            Ok(v) => v,
+            Err(ref e) if is_exhausted(e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        self.unwatch(wd.clone())?;
+        info!(
+            "moved {:?} back to inotify ({} path(s) degraded)",
+            path,
+            self.degraded.len()
+        );
+        Ok(Some(new_wd))
+    }
+
+    pub fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()> {
+        if self.degraded.remove(&wd) {
+            Metrics::fs().set_degraded_watches(self.degraded.len() as u64);
+        }
+
+        match wd {
+            WatchDescriptor::Inotify(_) => self.inotify.unwatch(wd),
+            WatchDescriptor::Poll(_) => self.poll.unwatch(wd),
+        }
+    }
+
+    pub fn read_events(&mut self, buffer: &mut [u8]) -> io::Result<Vec<WatchEvent>> {
+        // the polled paths are still read when inotify fails
+        let mut events = match self.inotify.read_events(buffer) {
+            Ok(events) => events,
+            Err(e) => {
+                error!("error reading inotify events: {}", e);
+                Vec::new()
+            }
+        };
+        events.append(&mut self.poll.read_events(buffer)?);
+        Ok(events)
+    }
+}
+
+// returns true if the error is inotify running out of watches
+fn is_exhausted(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOSPC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn watcher_exhausted_error() {
+        assert!(is_exhausted(&io::Error::from_raw_os_error(libc::ENOSPC)));
+        assert!(!is_exhausted(&io::Error::from_raw_os_error(libc::ENOENT)));
+    }
+
+    #[test]
+    fn watcher_retry_degraded() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path();
+        let mut watcher = Watcher::new(&WatchConfig::default()).unwrap();
+
+        // simulate a watch that fell back to polling
+        let wd = watcher.poll.watch(path).unwrap();
+        watcher.degraded.insert(wd.clone());
+        assert!(watcher.retry_due().is_none());
+        watcher.last_retry -= RETRY_INTERVAL;
+        assert_eq!(watcher.retry_due(), Some(vec![wd.clone()]));
+
+        let new_wd = watcher.retry(&wd, path).unwrap();
+        assert!(matches!(new_wd, Some(WatchDescriptor::Inotify(_))));
+        assert!(watcher.degraded.is_empty());
+        assert!(watcher.poll.unwatch(wd).is_err());
+    }
+}
@@ -1,61 +1,39 @@
-use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
-use std::ffi::OsString;
+use crate::cache::watch::{Backend, WatchDescriptor, WatchEvent};
+use inotify::{EventMask, Inotify, WatchMask};
 use std::io;
 use std::path::Path;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum WatchEvent {
-    Create {
-        wd: WatchDescriptor,
-        name: OsString,
-    },
-    Modify {
-        wd: WatchDescriptor,
-    },
-    Delete {
-        wd: WatchDescriptor,
-        name: OsString,
-    },
-    Move {
-        from_wd: WatchDescriptor,
-        from_name: OsString,
-        to_wd: WatchDescriptor,
-        to_name: OsString,
-    },
-    MovedFrom {
-        wd: WatchDescriptor,
-        name: OsString,
-        cookie: u32,
-    },
-    MovedTo {
-        wd: WatchDescriptor,
-        name: OsString,
-        cookie: u32,
-    },
-    Overflow,
-}
-
-pub struct Watcher {
+/// Watches paths with inotify
+pub struct InotifyBackend {
     inotify: Inotify,
 }
 
-impl Watcher {
+impl InotifyBackend {
     pub fn new() -> io::Result<Self> {
         Ok(Self {
             inotify: Inotify::init()?,
         })
     }
+}
 
-    pub fn watch<P: AsRef<Path>>(&mut self, path: P) -> io::Result<WatchDescriptor> {
+impl Backend for InotifyBackend {
+    fn watch(&mut self, path: &Path) -> io::Result<WatchDescriptor> {
         self.inotify
-            .add_watch(path.as_ref(), watch_mask(path.as_ref()))
+            .add_watch(path, watch_mask(path))
+            .map(WatchDescriptor::Inotify)
     }
 
-    pub fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()> {
-        self.inotify.rm_watch(wd)
+    fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()> {
+        match wd {
+            WatchDescriptor::Inotify(wd) => self.inotify.rm_watch(wd),
+            WatchDescriptor::Poll(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an inotify watch descriptor",
+            )),
+        }
     }
 
-    pub fn read_events(&mut self, buffer: &mut [u8]) -> io::Result<Vec<WatchEvent>> {
+    fn read_events(&mut self, buffer: &mut [u8]) -> io::Result<Vec<WatchEvent>> {
         let mut events = Vec::new();
         for raw_event in self.inotify.read_events(buffer)? {
             if raw_event.mask.contains(EventMask::MOVED_FROM) {
@@ -65,7 +43,7 @@ impl Watcher {
                         WatchEvent::MovedTo { wd, name, cookie } => {
                             if *cookie == raw_event.cookie {
                                 *event = WatchEvent::Move {
-                                    from_wd: raw_event.wd.clone(),
+                                    from_wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                                     from_name: raw_event.name.unwrap().to_os_string(),
                                     to_wd: wd.clone(),
                                     to_name: name.clone(),
@@ -80,7 +58,7 @@ impl Watcher {
 
                 if !found_match {
                     events.push(WatchEvent::MovedFrom {
-                        wd: raw_event.wd.clone(),
+                        wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                         name: raw_event.name.unwrap().to_os_string(),
                         cookie: raw_event.cookie,
                     });
@@ -94,7 +72,7 @@ impl Watcher {
                                 *event = WatchEvent::Move {
                                     from_wd: wd.clone(),
                                     from_name: name.clone(),
-                                    to_wd: raw_event.wd.clone(),
+                                    to_wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                                     to_name: raw_event.name.unwrap().to_os_string(),
                                 };
                                 found_match = true;
@@ -107,24 +85,24 @@ impl Watcher {
 
                 if !found_match {
                     events.push(WatchEvent::MovedTo {
-                        wd: raw_event.wd.clone(),
+                        wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                         name: raw_event.name.unwrap().to_os_string(),
                         cookie: raw_event.cookie,
                     });
                 }
             } else if raw_event.mask.contains(EventMask::CREATE) {
                 events.push(WatchEvent::Create {
-                    wd: raw_event.wd.clone(),
+                    wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                     name: raw_event.name.unwrap().to_os_string(),
                 });
             } else if raw_event.mask.contains(EventMask::DELETE) {
                 events.push(WatchEvent::Delete {
-                    wd: raw_event.wd.clone(),
+                    wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                     name: raw_event.name.unwrap().to_os_string(),
                 });
             } else if raw_event.mask.contains(EventMask::MODIFY) {
                 events.push(WatchEvent::Modify {
-                    wd: raw_event.wd.clone(),
+                    wd: WatchDescriptor::Inotify(raw_event.wd.clone()),
                 });
             } else if raw_event.mask.contains(EventMask::Q_OVERFLOW) {
                 events.push(WatchEvent::Overflow);
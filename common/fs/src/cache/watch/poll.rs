@@ -0,0 +1,238 @@
+use crate::cache::watch::{Backend, WatchDescriptor, WatchEvent};
+use hashbrown::HashMap;
+use std::ffi::OsString;
+use std::fs::{read_dir, symlink_metadata, Metadata};
+use std::io;
+use std::os::unix::fs::{DirEntryExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// the state of a polled path as of the last poll
+#[derive(Debug, PartialEq)]
+enum Snapshot {
+    // the inode of each child by name
+    Dir(HashMap<OsString, u64>),
+    File {
+        len: u64,
+        mtime: i64,
+        mtime_nsec: i64,
+    },
+    // symlinks and special files only change through their parent directory
+    Other,
+}
+
+impl Snapshot {
+    fn new(path: &Path, metadata: &Metadata) -> io::Result<Self> {
+        Ok(if metadata.is_dir() {
+            let mut children = HashMap::new();
+            for entry in read_dir(path)? {
+                let entry = entry?;
+                children.insert(entry.file_name(), entry.ino());
+            }
+            Snapshot::Dir(children)
+        } else if metadata.is_file() {
+            Snapshot::File {
+                len: metadata.len(),
+                mtime: metadata.mtime(),
+                mtime_nsec: metadata.mtime_nsec(),
+            }
+        } else {
+            Snapshot::Other
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Watch {
+    path: PathBuf,
+    snapshot: Snapshot,
+}
+
+/// Watches paths by comparing stat and directory listings every interval, for filesystems where
+/// inotify doesn't see every change
+pub struct PollBackend {
+    interval: Duration,
+    last_poll: Instant,
+    next_id: u64,
+    watches: HashMap<u64, Watch>,
+    ids: HashMap<PathBuf, u64>,
+}
+
+impl PollBackend {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_poll: Instant::now(),
+            next_id: 0,
+            watches: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    // compares every watched path against it's snapshot, updating the snapshots
+    fn poll(&mut self) -> Vec<WatchEvent> {
+        let mut modified = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+
+        for (id, watch) in self.watches.iter_mut() {
+            let wd = WatchDescriptor::Poll(*id);
+            // paths that can't be read are gone, their parent directory reports the delete
+            let current = match symlink_metadata(&watch.path)
+                .and_then(|metadata| Snapshot::new(&watch.path, &metadata))
+            {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            match (&watch.snapshot, &current) {
+                (Snapshot::Dir(before), Snapshot::Dir(after)) => {
+                    for (name, inode) in before.iter() {
+                        if after.get(name) != Some(inode) {
+                            removed.push((wd.clone(), name.clone(), *inode));
+                        }
+                    }
+                    for (name, inode) in after.iter() {
+                        if before.get(name) != Some(inode) {
+                            added.push((wd.clone(), name.clone(), *inode));
+                        }
+                    }
+                }
+                (Snapshot::File { .. }, Snapshot::File { .. }) if watch.snapshot != current => {
+                    modified.push(WatchEvent::Modify { wd });
+                }
+                _ => {}
+            }
+            watch.snapshot = current;
+        }
+
+        // an inode leaving one name and appearing under another was renamed
+        let mut events = modified;
+        for (from_wd, from_name, inode) in removed {
+            match added.iter().position(|(_, _, other)| *other == inode) {
+                Some(i) => {
+                    let (to_wd, to_name, _) = added.remove(i);
+                    events.push(WatchEvent::Move {
+                        from_wd,
+                        from_name,
+                        to_wd,
+                        to_name,
+                    });
+                }
+                None => events.push(WatchEvent::Delete {
+                    wd: from_wd,
+                    name: from_name,
+                }),
+            }
+        }
+        for (wd, name, _) in added {
+            events.push(WatchEvent::Create { wd, name });
+        }
+        events
+    }
+}
+
+impl Backend for PollBackend {
+    fn watch(&mut self, path: &Path) -> io::Result<WatchDescriptor> {
+        if let Some(id) = self.ids.get(path) {
+            return Ok(WatchDescriptor::Poll(*id));
+        }
+
+        let snapshot = Snapshot::new(path, &symlink_metadata(path)?)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids.insert(path.to_path_buf(), id);
+        self.watches.insert(
+            id,
+            Watch {
+                path: path.to_path_buf(),
+                snapshot,
+            },
+        );
+        Ok(WatchDescriptor::Poll(id))
+    }
+
+    fn unwatch(&mut self, wd: WatchDescriptor) -> io::Result<()> {
+        let watch = match wd {
+            WatchDescriptor::Poll(id) => self.watches.remove(&id),
+            WatchDescriptor::Inotify(_) => None,
+        };
+        match watch {
+            Some(watch) => {
+                self.ids.remove(&watch.path);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a polled watch descriptor",
+            )),
+        }
+    }
+
+    fn read_events(&mut self, _: &mut [u8]) -> io::Result<Vec<WatchEvent>> {
+        if self.watches.is_empty() || self.last_poll.elapsed() < self.interval {
+            return Ok(Vec::new());
+        }
+
+        self.last_poll = Instant::now();
+        Ok(self.poll())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{rename, File, OpenOptions};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn poll_create_modify_move_delete() {
+        let tempdir = TempDir::new().unwrap();
+        let dir = tempdir.path();
+        let file_path = dir.join("a.log");
+        File::create(&file_path).unwrap();
+
+        let mut backend = PollBackend::new(Duration::from_millis(0));
+        let dir_wd = backend.watch(dir).unwrap();
+        let file_wd = backend.watch(&file_path).unwrap();
+        assert_eq!(backend.watch(&file_path).unwrap(), file_wd);
+        assert!(backend.poll().is_empty());
+
+        let mut file = OpenOptions::new().append(true).open(&file_path).unwrap();
+        file.write_all(b"line\n").unwrap();
+        File::create(dir.join("b.log")).unwrap();
+        let events = backend.poll();
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&WatchEvent::Modify {
+            wd: file_wd.clone()
+        }));
+        assert!(events.contains(&WatchEvent::Create {
+            wd: dir_wd.clone(),
+            name: "b.log".into()
+        }));
+
+        rename(dir.join("b.log"), dir.join("c.log")).unwrap();
+        assert_eq!(
+            backend.poll(),
+            vec![WatchEvent::Move {
+                from_wd: dir_wd.clone(),
+                from_name: "b.log".into(),
+                to_wd: dir_wd.clone(),
+                to_name: "c.log".into(),
+            }]
+        );
+
+        std::fs::remove_file(dir.join("c.log")).unwrap();
+        assert_eq!(
+            backend.poll(),
+            vec![WatchEvent::Delete {
+                wd: dir_wd,
+                name: "c.log".into(),
+            }]
+        );
+
+        backend.unwatch(file_wd.clone()).unwrap();
+        assert!(backend.unwatch(file_wd).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The number of bytes needed to detect the compression of a file
+pub const MAGIC_LEN: u64 = 4;
+
+/// A compression format the tailer can decompress
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects the compression of a file from it's magic bytes, returns None for plain files
+    ///
+    /// The file must be at least MAGIC_LEN bytes long for the result to be meaningful
+    pub fn detect(file: &File) -> io::Result<Option<Self>> {
+        let mut magic = [0u8; MAGIC_LEN as usize];
+        file.read_exact_at(&mut magic, 0)?;
+
+        Ok(if magic[..2] == GZIP_MAGIC {
+            Some(Compression::Gzip)
+        } else if magic == ZSTD_MAGIC {
+            Some(Compression::Zstd)
+        } else {
+            None
+        })
+    }
+
+    /// Wraps a reader positioned at the start of a compressed stream in a decoder
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+}
@@ -0,0 +1,217 @@
+use crate::rule::{Rules, Status};
+use std::fs::{self, read_dir, read_link, read_to_string};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+// appended by the kernel to the fd links of files that have been deleted
+const DELETED_SUFFIX: &str = " (deleted)";
+
+/// Settings for finding the files processes in containers have open
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// Where procfs is mounted
+    pub proc_dir: PathBuf,
+    /// How often processes are scanned for newly opened files
+    pub interval: Duration,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            proc_dir: PathBuf::from("/proc"),
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+/// A regular file a process in a container has open
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenFile {
+    pub pid: u32,
+    /// The path of the file inside the process's root directory
+    pub path: PathBuf,
+    /// The path the file is reached through from outside the process's root directory
+    pub host_path: PathBuf,
+    /// The name of the process's executable
+    pub comm: String,
+    /// The cgroup the process belongs to, the unified hierarchy's if it has one
+    pub cgroup: String,
+}
+
+/// Lists the regular files open in processes whose root directory differs from ours and whose
+/// paths, as the process sees them, pass rules
+///
+/// Processes sharing our root directory write to paths the watched directories can cover, so they
+/// are skipped.
+pub fn scan(proc_dir: &Path, rules: &Rules) -> Vec<OpenFile> {
+    let own_root = match fs::metadata("/") {
+        Ok(v) => (v.dev(), v.ino()),
+        Err(e) => {
+            error!("unable to stat /: {:?}", e);
+            return Vec::new();
+        }
+    };
+    let processes = match read_dir(proc_dir) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("unable to list processes in {:?}: {:?}", proc_dir, e);
+            return Vec::new();
+        }
+    };
+
+    let mut found = Vec::new();
+    for process in processes.flatten() {
+        let pid = match process.file_name().to_str().and_then(|n| n.parse().ok()) {
+            Some(v) => v,
+            None => continue,
+        };
+        // processes can exit at any point of the scan, so whatever was found before is kept
+        if let Err(e) = scan_process(&process.path(), pid, own_root, rules, &mut found) {
+            debug!("unable to scan process {}: {:?}", pid, e);
+        }
+    }
+    found
+}
+
+// appends the files a process has open to found
+fn scan_process(
+    dir: &Path,
+    pid: u32,
+    own_root: (u64, u64),
+    rules: &Rules,
+    found: &mut Vec<OpenFile>,
+) -> io::Result<()> {
+    let root = dir.join("root");
+    let metadata = fs::metadata(&root)?;
+    if (metadata.dev(), metadata.ino()) == own_root {
+        return Ok(());
+    }
+
+    let mut paths = Vec::new();
+    for fd in read_dir(dir.join("fd"))?.flatten() {
+        let path = match read_link(fd.path()) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        // sockets, pipes and the like link to relative names such as socket:[1234]
+        let name = match path.to_str() {
+            Some(v) if path.is_absolute() && !v.ends_with(DELETED_SUFFIX) => v,
+            _ => continue,
+        };
+        if rules.passes(name) != Status::Ok || paths.contains(&path) {
+            continue;
+        }
+        paths.push(path);
+    }
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let comm = read_to_string(dir.join("comm"))?.trim_end().to_string();
+    let cgroup = parse_cgroup(&read_to_string(dir.join("cgroup"))?);
+    for path in paths {
+        let host_path = root.join(path.strip_prefix("/").unwrap_or(&path));
+        match fs::metadata(&host_path) {
+            Ok(metadata) if metadata.is_file() => {}
+            _ => continue,
+        }
+        found.push(OpenFile {
+            pid,
+            path,
+            host_path,
+            comm: comm.clone(),
+            cgroup: cgroup.clone(),
+        });
+    }
+    Ok(())
+}
+
+// returns the cgroup path of the unified hierarchy from the contents of /proc/<pid>/cgroup, or
+// the first hierarchy's if there is no unified one
+fn parse_cgroup(content: &str) -> String {
+    let mut cgroups = content
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ':');
+            let id = parts.next()?;
+            let path = parts.nth(1)?;
+            Some((id, path))
+        })
+        .peekable();
+    let first = cgroups.peek().map(|(_, path)| path.to_string());
+    match cgroups.find(|(id, _)| *id == "0") {
+        Some((_, path)) => path.to_string(),
+        None => first.unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::GlobRule;
+    use std::fs::{create_dir_all, write, File};
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn discovery_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("0::/kubepods/pod1/abc\n"),
+            "/kubepods/pod1/abc"
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/docker/abc\n11:memory:/docker/abc\n0::/\n"),
+            "/"
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/docker/abc\n11:memory:/docker/abc\n"),
+            "/docker/abc"
+        );
+        assert_eq!(parse_cgroup(""), "");
+    }
+
+    #[test]
+    fn discovery_scan() {
+        let tempdir = TempDir::new().unwrap();
+        let proc_dir = tempdir.path().join("proc");
+        let container = tempdir.path().join("container");
+        create_dir_all(container.join("var/app")).unwrap();
+        File::create(container.join("var/app/app.log")).unwrap();
+        File::create(container.join("var/app/data.db")).unwrap();
+
+        let process = proc_dir.join("42");
+        create_dir_all(process.join("fd")).unwrap();
+        symlink(&container, process.join("root")).unwrap();
+        write(process.join("comm"), "app\n").unwrap();
+        write(process.join("cgroup"), "0::/docker/abc\n").unwrap();
+        symlink("/var/app/app.log", process.join("fd/1")).unwrap();
+        // the same file on two descriptors is listed once
+        symlink("/var/app/app.log", process.join("fd/2")).unwrap();
+        symlink("/var/app/data.db", process.join("fd/3")).unwrap();
+        symlink("/var/app/old.log (deleted)", process.join("fd/4")).unwrap();
+        symlink("socket:[1234]", process.join("fd/5")).unwrap();
+
+        // a process sharing our root directory
+        let host = proc_dir.join("1");
+        create_dir_all(host.join("fd")).unwrap();
+        symlink("/", host.join("root")).unwrap();
+        symlink("/var/app/app.log", host.join("fd/1")).unwrap();
+        create_dir_all(proc_dir.join("self")).unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        assert_eq!(
+            scan(&proc_dir, &rules),
+            vec![OpenFile {
+                pid: 42,
+                path: "/var/app/app.log".into(),
+                host_path: process.join("root/var/app/app.log"),
+                comm: "app".into(),
+                cgroup: "/docker/abc".into(),
+            }]
+        );
+    }
+}
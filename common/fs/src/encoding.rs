@@ -0,0 +1,169 @@
+use crate::rule::{Rules, Status};
+use encoding_rs::{Encoding, UTF_8};
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+// the longest byte order mark, UTF-8's
+const MAX_BOM_LEN: usize = 3;
+// how much of the start of a file is looked at to tell if it's binary
+const SNIFF_LEN: usize = 8192;
+// the share of bytes in invalid UTF-8 sequences above which a file is considered binary
+const MAX_INVALID_RATIO: f64 = 0.3;
+
+/// The character encoding of the files matching a set of rules
+#[derive(Debug)]
+pub struct EncodingRule {
+    rules: Rules,
+    encoding: &'static Encoding,
+}
+
+impl EncodingRule {
+    /// Creates a rule decoding the files matching rules with the encoding named label, e.g
+    /// "latin1", "shift_jis" or "utf-16le"
+    pub fn new(rules: Rules, label: &str) -> Result<Self, String> {
+        match Encoding::for_label(label.trim().as_bytes()) {
+            Some(encoding) => Ok(Self { rules, encoding }),
+            None => Err(format!("unknown encoding {:?}", label)),
+        }
+    }
+
+    /// Returns true if the rule applies to the file at path
+    pub fn matches(&self, path: &str) -> bool {
+        self.rules.passes(path) == Status::Ok
+    }
+
+    /// The encoding of the files matching the rule
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+}
+
+/// Detects the encoding of a file from it's byte order mark, returns None if it has none
+pub fn detect_bom(file: &File) -> Option<&'static Encoding> {
+    let mut bom = [0u8; MAX_BOM_LEN];
+    let len = file.read_at(&mut bom, 0).ok()?;
+    Encoding::for_bom(&bom[..len]).map(|(encoding, _)| encoding)
+}
+
+/// Returns true if the start of a file looks like binary data rather than text, i.e it contains a
+/// NUL byte or is mostly invalid UTF-8
+pub fn is_binary(file: &File) -> bool {
+    let mut sample = vec![0u8; SNIFF_LEN];
+    let len = match file.read_at(&mut sample, 0) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    sample.truncate(len);
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let mut invalid = 0;
+    let mut rest = &sample[..];
+    while let Err(e) = std::str::from_utf8(rest) {
+        match e.error_len() {
+            Some(error_len) => {
+                invalid += error_len;
+                rest = &rest[e.valid_up_to() + error_len..];
+            }
+            // a character cut off by the end of the sample
+            None => break,
+        }
+    }
+    invalid as f64 / sample.len() as f64 > MAX_INVALID_RATIO
+}
+
+/// Transcodes a line to UTF-8, replacing invalid sequences and dropping a leading byte order mark
+pub fn decode(encoding: &'static Encoding, raw_line: Vec<u8>) -> String {
+    if encoding != UTF_8 {
+        return encoding.decode_with_bom_removal(&raw_line).0.into_owned();
+    }
+
+    // try to parse the raw data as utf8
+    // if that fails replace invalid chars with blank chars
+    // see String::from_utf8_lossy docs
+    let mut line = String::from_utf8(raw_line)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string());
+    if line.starts_with('\u{feff}') {
+        line.remove(0);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{UTF_16BE, UTF_16LE};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn encoding_unknown_label() {
+        assert!(EncodingRule::new(Rules::new(), "latin1").is_ok());
+        assert!(EncodingRule::new(Rules::new(), "not-an-encoding").is_err());
+    }
+
+    #[test]
+    fn encoding_detect_bom() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+
+        File::create(&path)
+            .unwrap()
+            .write_all(b"\xff\xfea\0")
+            .unwrap();
+        assert_eq!(detect_bom(&File::open(&path).unwrap()), Some(UTF_16LE));
+
+        File::create(&path)
+            .unwrap()
+            .write_all(b"\xfe\xff\0a")
+            .unwrap();
+        assert_eq!(detect_bom(&File::open(&path).unwrap()), Some(UTF_16BE));
+
+        File::create(&path).unwrap().write_all(b"plain").unwrap();
+        assert_eq!(detect_bom(&File::open(&path).unwrap()), None);
+    }
+
+    #[test]
+    fn encoding_is_binary() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+
+        File::create(&path)
+            .unwrap()
+            .write_all(b"\x7fELF\x02\x01\x01\0\0\0")
+            .unwrap();
+        assert!(is_binary(&File::open(&path).unwrap()));
+
+        // no NUL bytes but hardly any valid UTF-8
+        File::create(&path)
+            .unwrap()
+            .write_all(b"\x82\xff\xa0\xc3\x28\xfe\x9a\x37\xbb\xd1")
+            .unwrap();
+        assert!(is_binary(&File::open(&path).unwrap()));
+
+        File::create(&path)
+            .unwrap()
+            .write_all("caf\u{e9} ok\nline two\n".as_bytes())
+            .unwrap();
+        assert!(!is_binary(&File::open(&path).unwrap()));
+
+        // the odd invalid byte in otherwise readable text
+        File::create(&path)
+            .unwrap()
+            .write_all(b"caf\xe9 latin1 text\n")
+            .unwrap();
+        assert!(!is_binary(&File::open(&path).unwrap()));
+    }
+
+    #[test]
+    fn encoding_decode() {
+        let latin1 = Encoding::for_label(b"latin1").unwrap();
+        assert_eq!(decode(latin1, b"caf\xe9".to_vec()), "café");
+        assert_eq!(decode(UTF_16LE, b"\xff\xfeh\0i\0".to_vec()), "hi");
+        assert_eq!(decode(UTF_8, b"\xef\xbb\xbfok\xff".to_vec()), "ok\u{fffd}");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::rule::RegexRule;
+use http::types::body::{KeyValueMap, LineBuilder};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// The line field a named capture of a path is written to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    App,
+    Level,
+    /// A label named after the capture, lines have no tags of their own so tags are sent as labels
+    Tags,
+    /// A key of the line's meta, the capture's name is used as the key if None
+    Meta(Option<String>),
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    /// Parses "app", "level", "tags", "meta" or "meta.<key>"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_lowercase().as_str() {
+            "app" => Ok(Field::App),
+            "level" => Ok(Field::Level),
+            "tags" => Ok(Field::Tags),
+            "meta" => Ok(Field::Meta(None)),
+            _ if s.starts_with("meta.") && s.len() > "meta.".len() => {
+                Ok(Field::Meta(Some(s["meta.".len()..].to_string())))
+            }
+            _ => Err(format!(
+                "unknown field {:?}, expected app, level, tags, meta or meta.<key>",
+                s
+            )),
+        }
+    }
+}
+
+/// Pairs a path regex containing named capture groups with the line fields the captures are
+/// written to
+#[derive(Debug)]
+pub struct PathFieldRule {
+    regex: RegexRule,
+    fields: Vec<(String, Field)>,
+}
+
+impl PathFieldRule {
+    /// Creates a rule writing each named capture of pattern to the field paired with it's name
+    pub fn new(pattern: &str, fields: Vec<(String, Field)>) -> Result<Self, String> {
+        let regex = RegexRule::new(pattern).map_err(|e| format!("{:?}: {}", pattern, e))?;
+        if let Some((name, _)) = fields.iter().find(|(name, _)| !regex.has_capture(name)) {
+            return Err(format!(
+                "{:?} has no capture group named {:?}",
+                pattern, name
+            ));
+        }
+        Ok(Self { regex, fields })
+    }
+
+    /// Writes the captures of path to the fields of line, line is returned as is if path doesn't
+    /// match
+    pub fn apply(&self, path: &str, mut line: LineBuilder) -> LineBuilder {
+        let captures = match self.regex.named_captures(path) {
+            Some(v) => v,
+            None => return line,
+        };
+
+        for (name, value) in captures {
+            let field = match self.fields.iter().find(|(other, _)| other == name) {
+                Some((_, field)) => field,
+                None => continue,
+            };
+            line = match field {
+                Field::App => line.app(value),
+                Field::Level => line.level(value),
+                Field::Tags => {
+                    let labels = line.labels.take().unwrap_or_else(KeyValueMap::new);
+                    line.labels(labels.add(name.to_string(), value))
+                }
+                Field::Meta(Some(key)) => insert_meta(line, key, Value::String(value)),
+                Field::Meta(None) => insert_meta(line, name, Value::String(value)),
+            };
+        }
+        line
+    }
+}
+
+/// Sets a key of a line's meta, keeping any other keys it already has
+pub fn insert_meta<T: Into<String>>(mut line: LineBuilder, key: T, value: Value) -> LineBuilder {
+    let mut meta = match line.meta.take() {
+        Some(Value::Object(meta)) => meta,
+        _ => Map::new(),
+    };
+    meta.insert(key.into(), value);
+    line.meta(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_parse() {
+        assert_eq!("app".parse(), Ok(Field::App));
+        assert_eq!(" Level ".parse(), Ok(Field::Level));
+        assert_eq!("meta".parse(), Ok(Field::Meta(None)));
+        assert_eq!("meta.team".parse(), Ok(Field::Meta(Some("team".into()))));
+        assert!("meta.".parse::<Field>().is_err());
+        assert!("host".parse::<Field>().is_err());
+    }
+
+    #[test]
+    fn fields_from_path() {
+        let rule = PathFieldRule::new(
+            r"^/var/log/apps/(?P<team>[^/]+)/(?P<service>[^/]+)/(?P<env>[^/]+)\.log$",
+            vec![
+                ("service".into(), Field::App),
+                ("team".into(), Field::Tags),
+                ("env".into(), Field::Meta(Some("environment".into()))),
+            ],
+        )
+        .unwrap();
+
+        let line = rule.apply("/var/log/apps/infra/api/prod.log", LineBuilder::new());
+        assert_eq!(line.app, Some("api".into()));
+        assert_eq!(line.labels, Some(KeyValueMap::new().add("team", "infra")));
+        assert_eq!(line.meta, Some(serde_json::json!({"environment": "prod"})));
+
+        let line = rule.apply("/var/log/other.log", LineBuilder::new());
+        assert_eq!(line.app, None);
+
+        assert!(PathFieldRule::new("(?P<app>.*)", vec![("missing".into(), Field::App)]).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use crate::rule::{Rules, Status};
+use http::client::TIME_KEY;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+// the meta key the stream a message was written to is kept under, the time it was written is kept
+// under the key the client sends it as the line's timestamp from
+const STREAM_KEY: &str = "stream";
+// the fields of a docker json-file entry that are kept as the line's meta
+const DOCKER_META_KEYS: [&str; 2] = [STREAM_KEY, TIME_KEY];
+
+/// The format the lines of a file are written in by the log driver writing it, each line is
+/// unwrapped into the message it carries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineFormat {
+    /// Docker's json-file driver, a JSON object per line with the message under log
+    Docker,
+    /// CRI runtimes such as containerd and CRI-O, "<time> <stream> <tag> <message>" per line
+    /// where a P tag marks a message carrying on in the next line and an F tag the line ending it
+    Cri,
+}
+
+impl FromStr for LineFormat {
+    type Err = String;
+
+    /// Parses "docker", "json-file" or "cri"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "docker" | "json-file" => Ok(LineFormat::Docker),
+            "cri" => Ok(LineFormat::Cri),
+            _ => Err(format!("unknown format {:?}, expected docker or cri", s)),
+        }
+    }
+}
+
+impl LineFormat {
+    /// Unwraps the message of a line of len bytes read at offset, passing the messages it completes
+    /// to on_record, none while the message carries on in the next line
+    pub fn decode(
+        self,
+        line: String,
+        len: u64,
+        offset: u64,
+        partial: &mut Partial,
+        on_record: &mut impl FnMut(Record),
+    ) {
+        match self {
+            LineFormat::Docker => decode_docker(line, len, offset, partial, on_record),
+            LineFormat::Cri => decode_cri(line, len, offset, partial, on_record),
+        }
+    }
+}
+
+/// The line format of the files matching a set of rules
+#[derive(Debug)]
+pub struct FormatRule {
+    rules: Rules,
+    format: LineFormat,
+}
+
+impl FormatRule {
+    /// Creates a rule decoding the files matching rules as format
+    pub fn new(rules: Rules, format: LineFormat) -> Self {
+        Self { rules, format }
+    }
+
+    /// Returns true if the rule applies to the file at path
+    pub fn matches(&self, path: &str) -> bool {
+        self.rules.passes(path) == Status::Ok
+    }
+
+    /// The format of the files matching the rule
+    pub fn format(&self) -> LineFormat {
+        self.format
+    }
+}
+
+/// A message unwrapped from one or more lines of a file
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub line: String,
+    /// The fields of the message sent as the line's meta
+    pub meta: Map<String, Value>,
+    /// The length of the lines the message was read from
+    pub len: u64,
+    /// The offset of the first line the message was read from
+    pub offset: u64,
+}
+
+/// The start of the messages split across several lines, held until the lines ending them are
+/// read
+///
+/// Log drivers split the messages of each stream on their own, so the pieces of a stdout and a
+/// stderr message can be interleaved and a message is held for each stream.
+#[derive(Debug, Default)]
+pub struct Partial {
+    // the stream each held message was written to
+    records: Vec<(String, Record)>,
+    // the stream of the last piece
+    last: Option<String>,
+}
+
+impl Partial {
+    /// The offset of the first line of the held messages, None if there are none
+    pub fn start_offset(&self) -> Option<u64> {
+        self.records.iter().map(|(_, record)| record.offset).min()
+    }
+
+    // appends the next piece of a message written to stream, passing the message to on_record once
+    // it's complete, pieces without a stream carry on the message of the last stream
+    fn push(
+        &mut self,
+        stream: Option<&str>,
+        piece: Record,
+        complete: bool,
+        on_record: &mut impl FnMut(Record),
+    ) {
+        let stream = match stream {
+            Some(stream) => stream.to_string(),
+            None => match self.last.take() {
+                Some(stream) => stream,
+                None => return on_record(piece),
+            },
+        };
+        let held = self.records.iter().position(|(other, _)| *other == stream);
+        let record = match held {
+            // the fields of the first piece are kept for the whole message
+            Some(i) => {
+                let (_, mut record) = self.records.remove(i);
+                record.line.push_str(&piece.line);
+                record.len += piece.len;
+                record
+            }
+            None => piece,
+        };
+        if complete {
+            return on_record(record);
+        }
+        self.records.push((stream.clone(), record));
+        self.last = Some(stream);
+    }
+
+    // passes a line that isn't part of any message to on_record, after the message of the last
+    // stream it interrupts as it is
+    fn interrupt(&mut self, line: Record, on_record: &mut impl FnMut(Record)) {
+        if let Some(last) = self.last.take() {
+            if let Some(i) = self.records.iter().position(|(other, _)| *other == last) {
+                on_record(self.records.remove(i).1);
+            }
+        }
+        on_record(line)
+    }
+}
+
+// docker splits messages longer than 16KB into several entries, the log of every entry but the
+// last lacks the trailing new line
+fn decode_docker(
+    line: String,
+    len: u64,
+    offset: u64,
+    partial: &mut Partial,
+    on_record: &mut impl FnMut(Record),
+) {
+    let mut meta = Map::new();
+    let (line, stream, complete) = match serde_json::from_str(&line) {
+        Ok(Value::Object(mut entry)) => {
+            for key in DOCKER_META_KEYS.iter() {
+                if let Some(value) = entry.remove(*key) {
+                    meta.insert((*key).to_string(), value);
+                }
+            }
+            let stream = meta
+                .get(STREAM_KEY)
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            match entry.remove("log") {
+                Some(Value::String(mut log)) => {
+                    let complete = log.ends_with('\n');
+                    if complete {
+                        log.pop();
+                    }
+                    (log, stream, complete)
+                }
+                _ => (line, stream, true),
+            }
+        }
+        // lines that aren't entries are sent by themselves, ending the message they interrupt
+        _ => {
+            debug!("line isn't a docker json-file entry: {:?}", line);
+            let line = Record {
+                line,
+                meta,
+                len,
+                offset,
+            };
+            return partial.interrupt(line, on_record);
+        }
+    };
+
+    let piece = Record {
+        line,
+        meta,
+        len,
+        offset,
+    };
+    partial.push(stream.as_deref(), piece, complete, on_record)
+}
+
+// CRI runtimes split messages into lines tagged P, the line ending the message is tagged F
+fn decode_cri(
+    line: String,
+    len: u64,
+    offset: u64,
+    partial: &mut Partial,
+    on_record: &mut impl FnMut(Record),
+) {
+    let mut meta = Map::new();
+    let (line, stream, complete) = match parse_cri(&line) {
+        Some((time, stream, complete, message)) => {
+            meta.insert(STREAM_KEY.to_string(), Value::from(stream));
+            meta.insert(TIME_KEY.to_string(), Value::from(time));
+            (message.to_string(), stream, complete)
+        }
+        // lines that aren't CRI lines are sent by themselves, ending the message they interrupt
+        None => {
+            debug!("line isn't a CRI log line: {:?}", line);
+            let line = Record {
+                line,
+                meta,
+                len,
+                offset,
+            };
+            return partial.interrupt(line, on_record);
+        }
+    };
+
+    let piece = Record {
+        line,
+        meta,
+        len,
+        offset,
+    };
+    partial.push(Some(stream), piece, complete, on_record)
+}
+
+// splits a CRI line into it's time, stream, whether it ends a message and the message
+fn parse_cri(line: &str) -> Option<(&str, &str, bool, &str)> {
+    let mut parts = line.splitn(4, ' ');
+    let time = parts.next()?;
+    let stream = parts.next()?;
+    // tags are separated by colons, the first says whether the message is complete
+    let complete = match parts.next()?.split(':').next() {
+        Some("F") => true,
+        Some("P") => false,
+        _ => return None,
+    };
+    if time.is_empty() || !matches!(stream, "stdout" | "stderr") {
+        return None;
+    }
+    // an empty message may lack the space before it
+    Some((time, stream, complete, parts.next().unwrap_or("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decode(format: LineFormat, lines: &[&str]) -> Vec<Record> {
+        let mut partial = Partial::default();
+        let mut offset = 0;
+        let mut records = Vec::new();
+        for line in lines {
+            let len = line.len() as u64 + 1;
+            format.decode(line.to_string(), len, offset, &mut partial, &mut |record| {
+                records.push(record)
+            });
+            offset += len;
+        }
+        assert_eq!(partial.start_offset(), None);
+        records
+    }
+
+    #[test]
+    fn format_parse() {
+        assert_eq!("docker".parse(), Ok(LineFormat::Docker));
+        assert_eq!(" JSON-File ".parse(), Ok(LineFormat::Docker));
+        assert_eq!("CRI".parse(), Ok(LineFormat::Cri));
+        assert!("syslog".parse::<LineFormat>().is_err());
+    }
+
+    #[test]
+    fn format_docker() {
+        let records = decode(
+            LineFormat::Docker,
+            &[
+                r#"{"log":"hello\n","stream":"stdout","time":"2020-06-01T12:00:00.000000001Z"}"#,
+                r#"{"log":"oops\n","stream":"stderr","time":"2020-06-01T12:00:01Z","attrs":{"a":"b"}}"#,
+                "not json",
+            ],
+        );
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].line, "hello");
+        assert_eq!(
+            Value::Object(records[0].meta.clone()),
+            json!({"stream": "stdout", "time": "2020-06-01T12:00:00.000000001Z"})
+        );
+        assert_eq!(records[1].line, "oops");
+        assert_eq!(records[1].meta["stream"], "stderr");
+        assert_eq!(records[1].offset, records[0].len);
+        assert_eq!(records[2].line, "not json");
+        assert!(records[2].meta.is_empty());
+    }
+
+    #[test]
+    fn format_docker_split_lines() {
+        let first = "a".repeat(16 * 1024);
+        let lines = [
+            json!({"log": first, "stream": "stdout", "time": "1"}).to_string(),
+            json!({"log": "bcd", "stream": "stdout", "time": "2"}).to_string(),
+            json!({"log": "e\n", "stream": "stdout", "time": "3"}).to_string(),
+            json!({"log": "next\n", "stream": "stdout", "time": "4"}).to_string(),
+        ];
+        let lines: Vec<_> = lines.iter().map(|l| l.as_str()).collect();
+        let records = decode(LineFormat::Docker, &lines);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].line, format!("{}bcde", first));
+        assert_eq!(records[0].meta["time"], "1");
+        assert_eq!(records[0].offset, 0);
+        assert_eq!(
+            records[0].len,
+            lines[..3].iter().map(|l| l.len() as u64 + 1).sum::<u64>()
+        );
+        assert_eq!(records[1].line, "next");
+        assert_eq!(records[1].offset, records[0].len);
+    }
+
+    #[test]
+    fn format_docker_mixed_streams() {
+        let lines = [
+            json!({"log": "out ", "stream": "stdout", "time": "1"}).to_string(),
+            json!({"log": "err ", "stream": "stderr", "time": "2"}).to_string(),
+            json!({"log": "one\n", "stream": "stdout", "time": "3"}).to_string(),
+            json!({"log": "one", "stream": "stderr", "time": "4"}).to_string(),
+            "not json".to_string(),
+        ];
+        let lines: Vec<_> = lines.iter().map(|l| l.as_str()).collect();
+        let records = decode(LineFormat::Docker, &lines);
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].line, "out one");
+        assert_eq!(records[0].meta["stream"], "stdout");
+        assert_eq!(records[0].meta["time"], "1");
+        assert_eq!(records[0].offset, 0);
+        // a line that isn't an entry ends the message of the stream written to last
+        assert_eq!(records[1].line, "err one");
+        assert_eq!(records[1].meta["stream"], "stderr");
+        assert_eq!(records[1].meta["time"], "2");
+        assert_eq!(records[1].offset, lines[0].len() as u64 + 1);
+        assert_eq!(
+            records[1].len,
+            [1, 3]
+                .iter()
+                .map(|&i| lines[i].len() as u64 + 1)
+                .sum::<u64>()
+        );
+        // and is sent by itself
+        assert_eq!(records[2].line, "not json");
+        assert!(records[2].meta.is_empty());
+        assert_eq!(
+            records[2].offset,
+            lines[..4].iter().map(|l| l.len() as u64 + 1).sum::<u64>()
+        );
+    }
+
+    #[test]
+    fn format_cri() {
+        let records = decode(
+            LineFormat::Cri,
+            &[
+                "2020-06-01T12:00:00.000000001Z stdout F hello world",
+                "2020-06-01T12:00:01Z stderr P first ",
+                "2020-06-01T12:00:02Z stderr P second ",
+                "2020-06-01T12:00:03Z stderr F:x third",
+                "2020-06-01T12:00:04Z stdout F",
+                "2020-06-01T12:00:05Z stdout P cut off",
+                "not cri",
+            ],
+        );
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].line, "hello world");
+        assert_eq!(
+            Value::Object(records[0].meta.clone()),
+            json!({"stream": "stdout", "time": "2020-06-01T12:00:00.000000001Z"})
+        );
+        assert_eq!(records[1].line, "first second third");
+        assert_eq!(records[1].meta["stream"], "stderr");
+        assert_eq!(records[1].meta["time"], "2020-06-01T12:00:01Z");
+        assert_eq!(records[1].offset, records[0].len);
+        assert_eq!(records[2].line, "");
+        // a line that isn't CRI ends the message it interrupts
+        assert_eq!(records[3].line, "cut off");
+        assert_eq!(records[3].meta["stream"], "stdout");
+        assert_eq!(records[3].meta["time"], "2020-06-01T12:00:05Z");
+        // and is sent by itself
+        assert_eq!(records[4].line, "not cri");
+        assert!(records[4].meta.is_empty());
+        assert_eq!(records[4].offset, records[3].offset + records[3].len);
+    }
+
+    #[test]
+    fn format_cri_interleaved_streams() {
+        let records = decode(
+            LineFormat::Cri,
+            &[
+                "2020-06-01T12:00:00Z stdout P out ",
+                "2020-06-01T12:00:01Z stderr P err ",
+                "2020-06-01T12:00:02Z stdout P one ",
+                "2020-06-01T12:00:03Z stderr F one",
+                "2020-06-01T12:00:04Z stdout P two",
+                "not cri",
+            ],
+        );
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].line, "err one");
+        assert_eq!(records[0].meta["stream"], "stderr");
+        assert_eq!(records[0].meta["time"], "2020-06-01T12:00:01Z");
+        assert_eq!(records[0].offset, 35);
+        // a line that isn't CRI ends the message of the stream written to last
+        assert_eq!(records[1].line, "out one two");
+        assert_eq!(records[1].meta["stream"], "stdout");
+        assert_eq!(records[1].meta["time"], "2020-06-01T12:00:00Z");
+        assert_eq!(records[1].offset, 0);
+        // and is sent by itself
+        assert_eq!(records[2].line, "not cri");
+        assert!(records[2].meta.is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::rule::{Rules, Status};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::str::FromStr;
+
+/// How the records of a file are told apart
+#[derive(Debug, Clone, PartialEq)]
+pub enum Framing {
+    /// Records end with \n, a \r before it is removed as well
+    Newline,
+    /// Records end with \r\n, a \n on it's own is part of the record
+    CrLf,
+    /// Records end with a NUL character
+    Nul,
+    /// Records end with the given sequence
+    Delimiter(String),
+    /// Each record starts with it's length in bytes, in ASCII digits followed by a space, as in
+    /// the octet counting of RFC 6587
+    LengthPrefixed,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    /// Parses "newline", "crlf", "nul" or "length_prefixed", custom delimiters have no name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "newline" | "lf" => Ok(Framing::Newline),
+            "crlf" => Ok(Framing::CrLf),
+            "nul" | "null" => Ok(Framing::Nul),
+            "length_prefixed" | "length-prefixed" => Ok(Framing::LengthPrefixed),
+            _ => Err(format!(
+                "unknown framing {:?}, expected newline, crlf, nul or length_prefixed",
+                s
+            )),
+        }
+    }
+}
+
+impl Framing {
+    /// Returns a delimiter framing, the delimiter can't be empty
+    pub fn delimiter<T: Into<String>>(delimiter: T) -> Result<Self, String> {
+        let delimiter = delimiter.into();
+        if delimiter.is_empty() {
+            return Err("the delimiter can't be empty".into());
+        }
+        Ok(Framing::Delimiter(delimiter))
+    }
+
+    /// Returns the framing as the bytes it looks for in files of encoding
+    pub fn encode(&self, encoding: &'static Encoding) -> Frame {
+        let encode = |s: &str| Some(encode(encoding, s));
+        let (delimiter, trailer) = match self {
+            Framing::Newline => (encode("\n"), encode("\r")),
+            Framing::CrLf => (encode("\r\n"), None),
+            Framing::Nul => (encode("\0"), None),
+            Framing::Delimiter(delimiter) => (encode(delimiter), None),
+            Framing::LengthPrefixed => (None, None),
+        };
+        Frame {
+            delimiter,
+            trailer,
+            width: unit_width(encoding),
+            utf8: encoding == UTF_8,
+        }
+    }
+}
+
+/// A Framing in the bytes of a particular encoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The bytes ending a record, records are length prefixed if None
+    pub delimiter: Option<Vec<u8>>,
+    /// Removed from the end of a record once it's delimiter is removed
+    pub trailer: Option<Vec<u8>>,
+    /// The size of the encoding's code units, delimiters are only looked for at the start of one
+    pub width: usize,
+    /// Whether the encoding is UTF-8, records cut at the line limit are cut between characters
+    pub utf8: bool,
+}
+
+/// The framing of the files matching a set of rules
+#[derive(Debug)]
+pub struct FramingRule {
+    rules: Rules,
+    framing: Framing,
+}
+
+impl FramingRule {
+    /// Creates a rule framing the records of the files matching rules with framing
+    pub fn new(rules: Rules, framing: Framing) -> Self {
+        Self { rules, framing }
+    }
+
+    /// Returns true if the rule applies to the file at path
+    pub fn matches(&self, path: &str) -> bool {
+        self.rules.passes(path) == Status::Ok
+    }
+
+    /// The framing of the files matching the rule
+    pub fn framing(&self) -> &Framing {
+        &self.framing
+    }
+}
+
+// the bytes s is written as in encoding, encoding_rs only encodes UTF-16 as UTF-8 so it's done by
+// hand
+fn encode(encoding: &'static Encoding, s: &str) -> Vec<u8> {
+    if encoding == UTF_16LE {
+        s.encode_utf16()
+            .flat_map(|unit| unit.to_le_bytes().to_vec())
+            .collect()
+    } else if encoding == UTF_16BE {
+        s.encode_utf16()
+            .flat_map(|unit| unit.to_be_bytes().to_vec())
+            .collect()
+    } else {
+        encoding.encode(s).0.into_owned()
+    }
+}
+
+// the size of the code units of an encoding
+fn unit_width(encoding: &'static Encoding) -> usize {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        2
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_parse() {
+        assert_eq!("CRLF".parse(), Ok(Framing::CrLf));
+        assert_eq!(" nul ".parse(), Ok(Framing::Nul));
+        assert_eq!("length_prefixed".parse(), Ok(Framing::LengthPrefixed));
+        assert!("tab".parse::<Framing>().is_err());
+        assert_eq!(
+            Framing::delimiter("\x1e"),
+            Ok(Framing::Delimiter("\x1e".into()))
+        );
+        assert!(Framing::delimiter("").is_err());
+    }
+
+    #[test]
+    fn framing_encode() {
+        let frame = Framing::Newline.encode(UTF_8);
+        assert_eq!(frame.delimiter, Some(b"\n".to_vec()));
+        assert_eq!(frame.trailer, Some(b"\r".to_vec()));
+        assert_eq!(frame.width, 1);
+
+        let frame = Framing::CrLf.encode(UTF_16LE);
+        assert_eq!(frame.delimiter, Some(b"\r\0\n\0".to_vec()));
+        assert_eq!(frame.trailer, None);
+        assert_eq!(frame.width, 2);
+
+        assert_eq!(
+            Framing::Nul.encode(UTF_16BE).delimiter,
+            Some(b"\0\0".to_vec())
+        );
+        assert_eq!(Framing::LengthPrefixed.encode(UTF_8).delimiter, None);
+    }
+}
@@ -8,8 +8,26 @@ extern crate lazy_static;
 
 /// Prototype
 pub mod cache;
+/// Detection and decoding of compressed files
+pub mod compression;
+/// Finds the files processes in containers have open through /proc
+pub mod discovery;
+/// Character encodings of tailed files
+pub mod encoding;
 /// Contains the error type(s) for this crate
 pub mod error;
+/// Fills in line fields from the paths of tailed files
+pub mod fields;
+/// Unwraps the lines of files written by log drivers
+pub mod format;
+/// Splits the content of files into records
+pub mod framing;
+/// Joins related lines into a single event
+pub mod multiline;
+/// Persists the offsets of tailed files across restarts
+pub mod offsets;
+/// Rewrites the prefixes of the paths files are reported under
+pub mod remap;
 /// Traits and types for defining exclusion and inclusion rules
 pub mod rule;
 /// The source for filesystem generated lines
@@ -0,0 +1,193 @@
+use crate::rule::{Rules, Status};
+use pcre2::{bytes::Regex, Error as RegexError};
+use serde_json::{Map, Value};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const DEFAULT_MAX_LINES: usize = 500;
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A rule for joining consecutive lines of a file into a single event, e.g a stack trace
+#[derive(Debug)]
+pub struct MultilineRule {
+    rules: Rules,
+    start: Option<Regex>,
+    continuation: Option<Regex>,
+    max_lines: usize,
+    timeout: Duration,
+}
+
+impl MultilineRule {
+    /// Creates a rule for the files matching rules
+    ///
+    /// A line matching start begins a new event, a line matching continuation is appended to the
+    /// current event. If continuation is omitted every line not matching start is a continuation.
+    pub fn new(
+        rules: Rules,
+        start: Option<&str>,
+        continuation: Option<&str>,
+    ) -> Result<Self, RegexError> {
+        Ok(Self {
+            rules,
+            start: start.map(Regex::new).transpose()?,
+            continuation: continuation.map(Regex::new).transpose()?,
+            max_lines: DEFAULT_MAX_LINES,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets the maximum number of lines joined into one event
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = max_lines.max(1);
+        self
+    }
+
+    /// Sets how long an event is held waiting for more lines before it is sent
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns true if the rule applies to the file at path
+    pub fn matches(&self, path: &str) -> bool {
+        self.rules.passes(path) == Status::Ok
+    }
+
+    // returns true if the line belongs to the event before it
+    fn is_continuation(&self, line: &str) -> bool {
+        let is_match = |regex: &Regex| regex.is_match(line.as_bytes()).unwrap_or(false);
+
+        if let Some(ref start) = self.start {
+            if is_match(start) {
+                return false;
+            }
+        }
+
+        match self.continuation {
+            Some(ref continuation) => is_match(continuation),
+            None => self.start.is_some(),
+        }
+    }
+}
+
+/// Lines of a file buffered until the event they make up is complete
+#[derive(Debug)]
+pub struct PendingEvent {
+    rule: usize,
+    lines: Vec<String>,
+    // the meta of the first line
+    meta: Map<String, Value>,
+    paths: Vec<PathBuf>,
+    bytes: u64,
+    start_offset: u64,
+    updated: Instant,
+}
+
+impl PendingEvent {
+    /// Starts a new event for the rule at index rule, beginning at start_offset in the file
+    pub fn new(rule: usize, paths: &[PathBuf], start_offset: u64) -> Self {
+        Self {
+            rule,
+            lines: Vec::new(),
+            meta: Map::new(),
+            paths: paths.to_vec(),
+            bytes: 0,
+            start_offset,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Sets the meta the event is sent with
+    pub fn meta(mut self, meta: Map<String, Value>) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    /// Returns true if line should be added to this event rather than start a new one
+    pub fn accepts(&self, rules: &[MultilineRule], line: &str) -> bool {
+        let rule = &rules[self.rule];
+        !self.lines.is_empty() && self.lines.len() < rule.max_lines && rule.is_continuation(line)
+    }
+
+    /// Adds a line of line_len bytes to the event
+    pub fn push(&mut self, paths: &[PathBuf], line: String, line_len: u64) {
+        self.lines.push(line);
+        self.bytes += line_len;
+        self.paths = paths.to_vec();
+        self.updated = Instant::now();
+    }
+
+    /// Returns true if the event hasn't seen a new line within it's rule's timeout
+    pub fn is_expired(&self, rules: &[MultilineRule]) -> bool {
+        self.updated.elapsed() >= rules[self.rule].timeout
+    }
+
+    /// The offset of the first line of the event
+    pub fn start_offset(&self) -> u64 {
+        self.start_offset
+    }
+
+    /// The offset just past the last line of the event
+    pub fn end_offset(&self) -> u64 {
+        self.start_offset + self.bytes
+    }
+
+    /// Consumes the event returning the joined line, it's meta, the paths it was read from and
+    /// it's size
+    pub fn into_line(self) -> (String, Map<String, Value>, Vec<PathBuf>, u64) {
+        (self.lines.join("\n"), self.meta, self.paths, self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::GlobRule;
+
+    fn new_rule(start: Option<&str>, continuation: Option<&str>) -> MultilineRule {
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        MultilineRule::new(rules, start, continuation).unwrap()
+    }
+
+    #[test]
+    fn multiline_start_pattern() {
+        let rules = vec![new_rule(Some(r"^\d{4}-"), None)];
+        let mut event = PendingEvent::new(0, &[], 0);
+        event.push(&[], "2020-01-01 exception".into(), 21);
+
+        assert!(event.accepts(&rules, "    at com.example.Main"));
+        assert!(!event.accepts(&rules, "2020-01-01 next"));
+    }
+
+    #[test]
+    fn multiline_continuation_pattern() {
+        let rules = vec![new_rule(None, Some(r"^\s+"))];
+        let mut event = PendingEvent::new(0, &[], 0);
+        event.push(&[], "Traceback (most recent call last):".into(), 35);
+
+        assert!(event.accepts(&rules, "  File \"main.py\", line 1"));
+        assert!(!event.accepts(&rules, "ValueError: oops"));
+    }
+
+    #[test]
+    fn multiline_max_lines() {
+        let rules = vec![new_rule(Some(r"^\S"), None).max_lines(2)];
+        let mut event = PendingEvent::new(0, &[], 0);
+        event.push(&[], "start".into(), 6);
+        assert!(event.accepts(&rules, " one"));
+        event.push(&[], " one".into(), 5);
+        assert!(!event.accepts(&rules, " two"));
+
+        let (line, _, _, bytes) = event.into_line();
+        assert_eq!(line, "start\n one");
+        assert_eq!(bytes, 11);
+    }
+
+    #[test]
+    fn multiline_matches_path() {
+        let rule = new_rule(Some(r"^\S"), None);
+        assert!(rule.matches("/var/log/app.log"));
+        assert!(!rule.matches("/var/log/app.txt"));
+    }
+}
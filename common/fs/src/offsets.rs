@@ -0,0 +1,298 @@
+use crc32fast::Hasher;
+use hashbrown::HashMap;
+use std::fs::{create_dir_all, rename, File, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// the number of bytes at the head of a file that make up it's fingerprint
+const FINGERPRINT_LEN: u64 = 1024;
+// how often the offsets are written to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// the name of the file inside the db path
+const OFFSETS_FILE: &str = "offsets";
+
+/// Identifies a file by device and inode, independent of the path(s) it is reachable through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    dev: u64,
+    inode: u64,
+}
+
+impl FileId {
+    /// Builds the id of an open file
+    pub fn new(file: &File) -> io::Result<Self> {
+        Ok(Self::from_metadata(&file.metadata()?))
+    }
+
+    /// Builds the id of the file metadata was read from
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            dev: metadata.dev(),
+            inode: metadata.ino(),
+        }
+    }
+}
+
+/// A checksum over the head of a file, used to tell a reused inode apart from the file we tailed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    len: u64,
+    hash: u32,
+}
+
+impl Fingerprint {
+    /// Computes the fingerprint of the first FINGERPRINT_LEN bytes of a file
+    pub fn new(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len().min(FINGERPRINT_LEN);
+        Self::with_len(file, len)
+    }
+
+    // computes the fingerprint over exactly len bytes
+    fn with_len(file: &File, len: u64) -> io::Result<Self> {
+        let mut buf = vec![0u8; len as usize];
+        file.read_exact_at(&mut buf, 0)?;
+        let mut hasher = Hasher::new();
+        hasher.update(&buf);
+        Ok(Self {
+            len,
+            hash: hasher.finalize(),
+        })
+    }
+
+    /// Returns true if the head of the file still matches this fingerprint
+    pub fn matches(&self, file: &File) -> bool {
+        match Self::with_len(file, self.len) {
+            Ok(other) => other == *self,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns true if the fingerprint covers the full FINGERPRINT_LEN bytes
+    pub fn is_complete(&self) -> bool {
+        self.len == FINGERPRINT_LEN
+    }
+}
+
+/// Persists the offsets of tailed files so tailing can resume after a restart
+#[derive(Debug)]
+pub struct OffsetStore {
+    path: PathBuf,
+    // offsets loaded from disk at startup, consumed as files are initialized
+    saved: HashMap<FileId, (Fingerprint, u64)>,
+    // offsets of the files currently being tailed
+    live: HashMap<FileId, (Fingerprint, u64)>,
+    dirty: bool,
+    last_flush: Instant,
+}
+
+impl OffsetStore {
+    /// Opens the store inside dir, creating dir if it doesn't exist
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(OFFSETS_FILE);
+
+        let mut saved = HashMap::new();
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match parse_line(&line) {
+                        Some((id, value)) => {
+                            saved.insert(id, value);
+                        }
+                        None => warn!("ignoring malformed offset entry {:?}", line),
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        info!("loaded {} offset(s) from {:?}", saved.len(), path);
+        Ok(Self {
+            path,
+            saved,
+            live: HashMap::new(),
+            dirty: false,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Returns the saved offset for a file if the file is the same one that was tailed before
+    pub fn restore(&mut self, file: &File) -> Option<u64> {
+        let id = FileId::new(file).ok()?;
+        let (fingerprint, offset) = self.saved.remove(&id)?;
+        if !fingerprint.matches(file) {
+            return None;
+        }
+        Some(offset)
+    }
+
+    /// Records the current offset of a file
+    pub fn update(&mut self, file: &File, offset: u64) {
+        let id = match FileId::new(file) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("unable to stat file to store offset: {:?}", e);
+                return;
+            }
+        };
+
+        let fingerprint = match self.live.get(&id) {
+            Some((fingerprint, _)) if fingerprint.is_complete() => *fingerprint,
+            _ => match Fingerprint::new(file) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("unable to fingerprint file to store offset: {:?}", e);
+                    return;
+                }
+            },
+        };
+
+        self.live.insert(id, (fingerprint, offset));
+        self.dirty = true;
+    }
+
+    /// Moves the offset of an already recorded file
+    pub fn set_offset(&mut self, id: &FileId, offset: u64) {
+        if let Some((_, current)) = self.live.get_mut(id) {
+            *current = offset;
+            self.dirty = true;
+        }
+    }
+
+    /// Forgets the offset of a file
+    pub fn remove(&mut self, file: &File) {
+        if let Ok(id) = FileId::new(file) {
+            if self.live.remove(&id).is_some() {
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Writes the offsets to disk if they have changed and the flush interval has elapsed
+    pub fn flush_if_due(&mut self) {
+        if !self.dirty || self.last_flush.elapsed() < FLUSH_INTERVAL {
+            return;
+        }
+
+        if let Err(e) = self.flush() {
+            error!("unable to write offsets to {:?}: {:?}", self.path, e);
+        }
+    }
+
+    /// Writes the offsets to disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        // write to a temporary file and rename it so a crash can't leave a partial file behind
+        let tmp_path = self.path.with_extension("tmp");
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        let mut writer = BufWriter::new(file);
+        for (id, (fingerprint, offset)) in self.live.iter() {
+            writeln!(
+                writer,
+                "{} {} {} {} {}",
+                id.dev, id.inode, fingerprint.len, fingerprint.hash, offset
+            )?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for OffsetStore {
+    // offsets recorded since the last flush would otherwise be lost on shutdown
+    fn drop(&mut self) {
+        if !self.dirty {
+            return;
+        }
+
+        if let Err(e) = self.flush() {
+            error!("unable to write offsets to {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+// parses a line in the format "<dev> <inode> <fingerprint len> <fingerprint hash> <offset>"
+fn parse_line(line: &str) -> Option<(FileId, (Fingerprint, u64))> {
+    let mut fields = line.split_whitespace();
+    let dev = fields.next()?.parse().ok()?;
+    let inode = fields.next()?.parse().ok()?;
+    let len = fields.next()?.parse().ok()?;
+    let hash = fields.next()?.parse().ok()?;
+    let offset = fields.next()?.parse().ok()?;
+    Some((FileId { dev, inode }, (Fingerprint { len, hash }, offset)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn offsets_restore_after_reopen() {
+        let tempdir = TempDir::new().unwrap();
+        let file_path = tempdir.path().join("test.log");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all(b"first line\nsecond line\n").unwrap();
+        let file = File::open(&file_path).unwrap();
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        store.update(&file, 11);
+        store.flush().unwrap();
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        assert_eq!(store.restore(&file), Some(11));
+        // offsets are only restored once
+        assert_eq!(store.restore(&file), None);
+    }
+
+    #[test]
+    fn offsets_flush_on_drop() {
+        let tempdir = TempDir::new().unwrap();
+        let file_path = tempdir.path().join("test.log");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all(b"first line\nsecond line\n").unwrap();
+        let file = File::open(&file_path).unwrap();
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        store.update(&file, 11);
+        // well within the flush interval
+        store.flush_if_due();
+        drop(store);
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        assert_eq!(store.restore(&file), Some(11));
+    }
+
+    #[test]
+    fn offsets_ignore_changed_file() {
+        let tempdir = TempDir::new().unwrap();
+        let file_path = tempdir.path().join("test.log");
+        let mut file = File::create(&file_path).unwrap();
+        file.write_all(b"first line\nsecond line\n").unwrap();
+        let read_file = File::open(&file_path).unwrap();
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        store.update(&read_file, 11);
+        store.flush().unwrap();
+
+        // same inode, different content
+        file.set_len(0).unwrap();
+        file.write_all_at(b"other content\n", 0).unwrap();
+
+        let mut store = OffsetStore::open(tempdir.path().join("db")).unwrap();
+        assert_eq!(store.restore(&read_file), None);
+    }
+}
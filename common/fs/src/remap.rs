@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+
+/// Rewrites the prefixes of the paths files are reported under, e.g so the host's /var/log
+/// mounted at /host/var/log inside a container is reported as /var/log
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathMap {
+    // watched prefix -> reported prefix, longest watched prefix first
+    prefixes: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PathMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports the paths under watched as being under reported instead, paths under several
+    /// watched prefixes are rewritten by the longest one
+    pub fn add<W: Into<PathBuf>, R: Into<PathBuf>>(&mut self, watched: W, reported: R) {
+        self.prefixes.push((watched.into(), reported.into()));
+        self.prefixes
+            .sort_by_key(|(watched, _)| Reverse(watched.components().count()));
+    }
+
+    /// Adds a rule written as "<watched prefix>=<reported prefix>"
+    pub fn add_rule(&mut self, rule: &str) -> Result<(), String> {
+        let mut parts = rule.splitn(2, '=').map(str::trim);
+        match (parts.next(), parts.next()) {
+            (Some(watched), Some(reported))
+                if watched.starts_with('/') && reported.starts_with('/') =>
+            {
+                self.add(watched, reported);
+                Ok(())
+            }
+            _ => Err(format!(
+                "invalid path mapping {:?}, expected <watched prefix>=<reported prefix>",
+                rule
+            )),
+        }
+    }
+
+    /// Returns the path a file at path is reported under
+    pub fn apply<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        for (watched, reported) in self.prefixes.iter() {
+            if let Ok(rest) = path.strip_prefix(watched) {
+                return Cow::Owned(reported.join(rest));
+            }
+        }
+        Cow::Borrowed(path)
+    }
+
+    /// Returns the path a file reported under path is watched at, the reverse of apply
+    pub fn watched<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        // the prefixes are sorted by their watched side, so the longest reported one is looked for
+        let longest = self
+            .prefixes
+            .iter()
+            .filter_map(|(watched, reported)| Some((watched, path.strip_prefix(reported).ok()?)))
+            .min_by_key(|(_, rest)| rest.components().count());
+        match longest {
+            Some((watched, rest)) => Cow::Owned(watched.join(rest)),
+            None => Cow::Borrowed(path),
+        }
+    }
+
+    /// Same as apply for paths given as strings
+    pub fn apply_str<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        match self.apply(Path::new(path)) {
+            Cow::Borrowed(_) => Cow::Borrowed(path),
+            Cow::Owned(path) => Cow::Owned(path.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remap_prefixes() {
+        let mut map = PathMap::new();
+        map.add_rule("/host/var/log=/var/log").unwrap();
+        map.add_rule(" /host = / ").unwrap();
+
+        assert_eq!(
+            map.apply(Path::new("/host/var/log/containers/a.log")),
+            Path::new("/var/log/containers/a.log")
+        );
+        assert_eq!(map.apply(Path::new("/host/var/log")), Path::new("/var/log"));
+        assert_eq!(
+            map.apply(Path::new("/host/etc/app.log")),
+            Path::new("/etc/app.log")
+        );
+        // prefixes match whole components only
+        assert_eq!(
+            map.apply(Path::new("/hostname/a.log")),
+            Path::new("/hostname/a.log")
+        );
+        assert_eq!(map.apply_str("/srv/a.log"), "/srv/a.log");
+
+        assert_eq!(
+            map.watched(Path::new("/var/log/containers")),
+            Path::new("/host/var/log/containers")
+        );
+        assert_eq!(map.watched(Path::new("/etc")), Path::new("/host/etc"));
+
+        assert!(map.add_rule("/host/var/log").is_err());
+        assert!(map.add_rule("host=/").is_err());
+    }
+}
@@ -116,6 +116,34 @@ impl RegexRule {
             inner: Regex::new(pattern.into())?,
         })
     }
+
+    /// Returns the name and value of each named capture group that took part in matching value,
+    /// None if value doesn't match
+    pub fn named_captures(&self, value: &str) -> Option<Vec<(&str, String)>> {
+        let captures = self.inner.captures(value.as_bytes()).ok()??;
+        Some(
+            self.inner
+                .capture_names()
+                .iter()
+                .filter_map(|name| name.as_ref())
+                .filter_map(|name| {
+                    let value = captures.name(name)?;
+                    Some((
+                        name.as_str(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    ))
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns true if the regex has a capture group named name
+    pub fn has_capture(&self, name: &str) -> bool {
+        self.inner
+            .capture_names()
+            .iter()
+            .any(|other| other.as_ref().map(|other| other.as_str()) == Some(name))
+    }
 }
 
 impl Rule for RegexRule {
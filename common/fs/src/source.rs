@@ -1,5 +1,5 @@
 use crate::rule::Rules;
-use crate::tail::Tailer;
+use crate::tail::{TailConfig, Tailer};
 
 use std::path::PathBuf;
 
@@ -11,9 +11,9 @@ pub struct FSSource {
     tailer: Tailer,
 }
 impl FSSource {
-    pub fn new<R: Into<Rules>>(paths: Vec<PathBuf>, rules: R) -> FSSource {
+    pub fn new<R: Into<Rules>>(paths: Vec<PathBuf>, rules: R, config: TailConfig) -> FSSource {
         FSSource {
-            tailer: Tailer::new(paths, rules.into()),
+            tailer: Tailer::new(paths, rules.into(), config),
         }
     }
 }
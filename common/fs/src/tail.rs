@@ -1,27 +1,391 @@
-use crate::cache::entry::Entry;
+use crate::cache::entry::{Entry, EntryKey};
 use crate::cache::event::Event;
+use crate::cache::watch::WatchConfig;
 use crate::cache::FileSystem;
-use crate::rule::Rules;
+use crate::compression::{Compression, MAGIC_LEN};
+use crate::discovery::{self, DiscoveryConfig, OpenFile};
+use crate::encoding::{self, EncodingRule};
+use crate::fields::{self, PathFieldRule};
+use crate::format::{FormatRule, Partial, Record};
+use crate::framing::{Frame, Framing, FramingRule};
+use crate::multiline::{MultilineRule, PendingEvent};
+use crate::offsets::{FileId, Fingerprint, OffsetStore};
+use crate::remap::PathMap;
+use crate::rule::{Rules, Status};
+use encoding_rs::{Encoding, UTF_8};
+use hashbrown::{HashMap, HashSet};
 use http::types::body::LineBuilder;
 use metrics::Metrics;
+use serde_json::{Map, Value};
 use std::cell::RefCell;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// the offset of a compressed file that has been decompressed in full
+const COMPLETE: u64 = u64::MAX;
+// how long a compressed file must go without being written to before it's decompressed
+const COMPRESSED_QUIET_PERIOD: Duration = Duration::from_secs(1);
+// how long a deleted file can go without being written to before it's released
+const DRAIN_QUIET_PERIOD: Duration = Duration::from_secs(5);
+// the meta key the other paths of a file are listed under when links are deduped
+const ALIASES_KEY: &str = "aliases";
+// appended to lines cut short by the line limit
+const TRUNCATED_MARKER: &str = " [truncated]";
+// the longest length of a length prefixed record, in digits and the space after them, along with
+// the new lines that may come before it
+const MAX_PREFIX_LEN: usize = 24;
+
+// the content type of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Plain,
+    Compressed(Compression),
+    // plain content that looks like binary data, these files aren't read
+    Binary,
+}
+
+impl Format {
+    // returns None if the file is too short to tell yet
+    fn detect(file_handle: &File) -> Option<Self> {
+        match file_handle.metadata().map(|m| m.len()) {
+            Ok(len) if len >= MAGIC_LEN => match Compression::detect(file_handle) {
+                Ok(Some(compression)) => Some(Format::Compressed(compression)),
+                Ok(None) => Some(Format::Plain),
+                Err(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// the state kept for each file being tailed
+#[derive(Debug, Default)]
+struct FileState {
+    // bytes from the beginning of the file we have read, for compressed files this counts
+    // decompressed bytes
+    offset: u64,
+    format: Option<Format>,
+    // identifies the content of the file, the inode is fixed by the open file handle so a changed
+    // fingerprint means the file was truncated or overwritten in place
+    fingerprint: Option<Fingerprint>,
+    // true while in the middle of a line longer than the line limit
+    oversize: bool,
+    // set along with the format, the file is read as UTF-8 if None
+    encoding: Option<&'static Encoding>,
+    // set along with the encoding, records end with a new line if None
+    frame: Option<Frame>,
+    // the start of a message split across lines by the log driver writing the file
+    partial: Partial,
+    // sent as meta with every line of the file
+    meta: Map<String, Value>,
+    // kept while a compressed file is read over several turns
+    decoder: Option<Decoder>,
+}
+
+impl FileState {
+    // returns true if there is data in a file of len bytes that hasn't been read yet
+    fn is_behind(&self, len: u64) -> bool {
+        match self.format {
+            Some(Format::Compressed(_)) => self.offset != COMPLETE,
+            _ => self.offset < len,
+        }
+    }
+}
+
+// a compressed file part way through being decompressed, so a file that ran out of budget carries
+// on from where it stopped rather than being decompressed from the start again on it's next turn
+struct Decoder {
+    reader: BufReader<Box<dyn Read>>,
+    // the offset of the decompressed data the reader is at
+    offset: u64,
+}
+
+impl Decoder {
+    // starts decompressing a file from the start, the handle is cloned so the decoder can outlive
+    // the borrow of the file
+    fn new(compression: Compression, file_handle: &File) -> io::Result<Self> {
+        let mut file = file_handle.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Self {
+            reader: BufReader::new(compression.decoder(file)?),
+            offset: 0,
+        })
+    }
+}
+
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+/// Where tailing starts in a file that already has content when it's first seen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitialPosition {
+    /// Read the whole file
+    Start,
+    /// Only read lines written from now on
+    End,
+    /// Read the whole file if it's smaller than the given number of bytes, otherwise start at the
+    /// end
+    Threshold(u64),
+    /// Read the whole file if it was modified within the given window, otherwise start at the end
+    Lookback(Duration),
+}
+
+impl InitialPosition {
+    // returns true if the file should be read from the beginning rather than the end
+    fn reads_from_start(self, metadata: &Metadata) -> bool {
+        match self {
+            InitialPosition::Start => true,
+            InitialPosition::End => false,
+            InitialPosition::Threshold(bytes) => metadata.len() < bytes,
+            // an mtime in the future counts as recent
+            InitialPosition::Lookback(window) => match metadata.modified() {
+                Ok(mtime) => mtime.elapsed().map(|age| age <= window).unwrap_or(true),
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+impl Default for InitialPosition {
+    fn default() -> Self {
+        InitialPosition::Threshold(8192)
+    }
+}
+
+impl FromStr for InitialPosition {
+    type Err = String;
+
+    /// Parses "start", "end", "threshold(<bytes>)" or "lookback(<duration>)" where duration is a
+    /// number of seconds optionally suffixed with s, m, h or d
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (mode, arg) = match s.find('(') {
+            Some(i) if s.ends_with(')') => (&s[..i], Some(s[i + 1..s.len() - 1].trim())),
+            _ => (s, None),
+        };
+
+        match (mode.trim().to_lowercase().as_str(), arg) {
+            ("start", None) => Ok(InitialPosition::Start),
+            ("end", None) => Ok(InitialPosition::End),
+            ("threshold", Some(bytes)) => bytes
+                .parse()
+                .map(InitialPosition::Threshold)
+                .map_err(|_| format!("invalid threshold {:?}, expected bytes", bytes)),
+            ("lookback", Some(duration)) => parse_duration(duration)
+                .map(InitialPosition::Lookback)
+                .ok_or_else(|| format!("invalid lookback {:?}, expected e.g 30m or 2h", duration)),
+            _ => Err(format!(
+                "unknown initial position {:?}, expected start, end, threshold(<bytes>) or lookback(<duration>)",
+                s
+            )),
+        }
+    }
+}
+
+// parses a number of seconds with an optional s, m, h or d unit suffix
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, "s"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    value
+        .parse::<u64>()
+        .ok()
+        .map(|v| Duration::from_secs(v * secs))
+}
+
+/// What happens to lines longer than the line limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OversizeMode {
+    /// Send the start of the line with a marker and drop the rest
+    Truncate,
+    /// Send the line as multiple lines of at most the limit
+    Split,
+}
+
+impl FromStr for OversizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "truncate" => Ok(OversizeMode::Truncate),
+            "split" => Ok(OversizeMode::Split),
+            _ => Err(format!(
+                "unknown oversize line mode {:?}, expected truncate or split",
+                s
+            )),
+        }
+    }
+}
+
+/// Bounds the number of bytes buffered for a single line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineLimit {
+    /// The maximum length of a line, excluding the new line
+    pub max_bytes: usize,
+    pub mode: OversizeMode,
+}
+
+/// Bounds how much of a file is read before the other files get a turn, the rest of the file is
+/// read on the next call to Tailer::process
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReadBudget {
+    /// The most bytes read from a file per turn, unbounded if None
+    pub bytes: Option<u64>,
+    /// The most lines read from a file per turn, unbounded if None
+    pub lines: Option<u64>,
+}
+
+impl ReadBudget {
+    // returns true once either bound has been reached
+    fn is_spent(self, bytes: u64, lines: u64) -> bool {
+        matches!(self.bytes, Some(max) if bytes >= max)
+            || matches!(self.lines, Some(max) if lines >= max)
+    }
+}
+
+/// Settings for how the Tailer reads files
+#[derive(Debug, Default)]
+pub struct TailConfig {
+    /// The directory offsets are persisted to, offsets are kept in memory only if None
+    pub db_path: Option<PathBuf>,
+    /// Rules for joining lines into multiline events, the first rule matching a file is used
+    pub multiline: Vec<MultilineRule>,
+    /// Where tailing starts in files that exist at startup or have been truncated
+    pub initial_position: InitialPosition,
+    /// How the watched directories are watched for changes
+    pub watch: WatchConfig,
+    /// The longest line read in one piece, lines are unbounded if None
+    pub line_limit: Option<LineLimit>,
+    /// Rules for the character encoding of files, the first rule matching a file is used and
+    /// files without a rule are read as UTF-8 unless they start with a byte order mark
+    pub encodings: Vec<EncodingRule>,
+    /// How much of a file is read per turn before the other files with data to read get theirs
+    pub budget: ReadBudget,
+    /// How long a file can go without being read before it's handle is closed, handles are kept
+    /// open if None
+    pub idle_timeout: Option<Duration>,
+    /// The most files kept open at once, the least recently read are closed first
+    pub max_open_files: Option<usize>,
+    /// How long deleted files that are still open elsewhere are read for after they are deleted,
+    /// they are released earlier if they stop being written to, deleted files are released
+    /// straight away if None
+    pub delete_grace: Option<Duration>,
+    /// Rules for filling in line fields from the named captures of path regexes, every rule
+    /// matching a path is applied in order
+    pub path_fields: Vec<PathFieldRule>,
+    /// Send each line once for files reachable through several paths, under the first path with
+    /// the others attached as meta, rather than once per path
+    pub dedupe_links: bool,
+    /// Files that are read even if they look binary, files with an encoding or framing rule are
+    /// never considered binary
+    pub binary_include: Rules,
+    /// Rules for the format log drivers write the lines of files in, the first rule matching a
+    /// file is used and the lines of files without a rule are sent as read
+    pub formats: Vec<FormatRule>,
+    /// Rules for how the records of files are told apart, the first rule matching a file is used
+    /// and records of files without a rule end with a new line
+    pub framing: Vec<FramingRule>,
+    /// How files open in containers are found through /proc, files are only found in the watched
+    /// directories if None
+    pub discovery: Option<DiscoveryConfig>,
+    /// Rewrites the paths files are reported under, rules are evaluated against the rewritten
+    /// paths
+    pub path_map: PathMap,
+}
+
+// a file found open in a container
+struct Discovered {
+    file: File,
+    // the path the file is read through, and the path the process has it open under
+    host_path: PathBuf,
+    paths: Vec<PathBuf>,
+    state: FileState,
+}
+
+// a deleted file that is still read because a writer may have it open
+struct Draining {
+    file: File,
+    // the direct path and paths the file had when it was deleted
+    path: PathBuf,
+    paths: Vec<PathBuf>,
+    state: FileState,
+    deleted: Instant,
+    // when the file last had new data
+    last_read: Instant,
+}
 
 /// Tails files on a filesystem by inheriting events from a Watcher
 pub struct Tailer {
-    // tracks the offset (bytes from the beginning of the file we have read) of file(s)
-    fs: Rc<RefCell<FileSystem<u64>>>,
+    // tracks the offset and format of file(s)
+    fs: Rc<RefCell<FileSystem<FileState>>>,
+    config: TailConfig,
+    // persists the offsets so tailing can resume after a restart
+    offsets: Option<OffsetStore>,
+    // multiline events waiting on more lines, keyed by file and the path it was read through
+    pending: HashMap<(FileId, PathBuf), PendingEvent>,
+    // the direct paths of files that ran out of budget with data left to read, in turn order
+    backlog: VecDeque<PathBuf>,
+    // the direct paths of the files read during the current call to process
+    served: HashSet<PathBuf>,
+    // when each file with an open handle was last read, keyed by entry so renames carry it along
+    open: HashMap<EntryKey<FileState>, Instant>,
+    // deleted files still being read
+    draining: Vec<Draining>,
+    // the direct paths of the hard links to each file when links are deduped, the first link is
+    // the one that reads the file
+    links: HashMap<FileId, Vec<PathBuf>>,
+    // files found open in containers, read by polling as they are outside the watched directories
+    discovered: HashMap<FileId, Discovered>,
+    last_discovery: Option<Instant>,
 }
 
 impl Tailer {
     /// Creates new instance of Tailer
-    pub fn new(watched_dirs: Vec<PathBuf>, rules: Rules) -> Self {
-        let fs = FileSystem::new(watched_dirs, rules);
+    pub fn new(watched_dirs: Vec<PathBuf>, rules: Rules, config: TailConfig) -> Self {
+        let offsets = config.db_path.as_ref().and_then(|path| match OffsetStore::open(path) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!(
+                    "unable to open offset store in {:?}, offsets will not persist across restarts: {:?}",
+                    path, e
+                );
+                None
+            }
+        });
+        let path_map = config.path_map.clone();
+        let fs = FileSystem::with_config(watched_dirs, rules, &config.watch, path_map);
         Self {
             fs: Rc::new(RefCell::new(fs)),
+            config,
+            offsets,
+            pending: HashMap::new(),
+            backlog: VecDeque::new(),
+            served: HashSet::new(),
+            open: HashMap::new(),
+            draining: Vec::new(),
+            links: HashMap::new(),
+            discovered: HashMap::new(),
+            last_discovery: None,
         }
     }
     /// Runs the main logic of the tailer, this can only be run once so Tailer is consumed
@@ -29,142 +393,2312 @@ impl Tailer {
     where
         F: FnMut(Vec<LineBuilder>),
     {
+        // files with events get their turn first, the backlog is served after them
+        let backlog = std::mem::take(&mut self.backlog);
+        self.served.clear();
+        let budget = self.config.budget;
         self.fs.clone().borrow_mut().read_events(&mut |fs, event| {
             match event {
-                Event::Initialize(mut entry_ptr) => {
+                Event::Initialize(key) => {
                     // will initiate a file to it's current length
-                    let entry = unsafe { entry_ptr.as_mut() };
+                    let entry = match fs.entry(key) {
+                        Some(v) => v,
+                        None => return,
+                    };
                     let path = fs.resolve_direct_path(entry);
+                    let paths = fs.resolve_valid_paths(entry);
+                    let id = match entry.file_handle() {
+                        Some(v) => v.id(),
+                        None => return,
+                    };
+
+                    let paths = match self.link_paths(fs, id, &path) {
+                        Some(aliases) => [paths, aliases].concat(),
+                        // another link to the file reads it
+                        None => return,
+                    };
+                    if !self.open_handle(fs, key, &path) {
+                        return;
+                    }
+                    let (file_handle, data) = match open_file(fs, key) {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    let metadata = match path.metadata() {
+                        Ok(v) => v,
+                        Err(e) => {
+                            error!("unable to stat {:?}: {:?}", path, e);
+                            return;
+                        }
+                    };
+                    let len = metadata.len();
+                    data.format = Format::detect(file_handle);
+                    let restored = self.offsets.as_mut().and_then(|o| o.restore(file_handle));
+                    let reads_from_start = self.config.initial_position.reads_from_start(&metadata);
+                    data.offset = match restored {
+                        Some(offset) => {
+                            info!("initialized {:?} with restored offset {}", path, offset);
+                            offset
+                        }
+                        None if reads_from_start => {
+                            info!("initialized {:?} with offset 0", path);
+                            0
+                        }
+                        // compressed files are all or nothing, skip them like the start of a
+                        // plain file
+                        None if matches!(data.format, Some(Format::Compressed(_))) => {
+                            info!("initialized compressed file {:?} as already read", path);
+                            COMPLETE
+                        }
+                        None => {
+                            info!("initialized {:?} with offset {}", path, len);
+                            len
+                        }
+                    };
 
-                    if let Entry::File { ref mut data, .. } = entry {
-                        let mut len = path.metadata().map(|m| m.len()).unwrap_or(0);
-                        if len < 8192 { len = 0 }
-                        info!("initialized {:?} with offset {}", path, len,);
-                        *data = len;
+                    data.fingerprint = Fingerprint::new(file_handle).ok();
+                    if let Some(offsets) = self.offsets.as_mut() {
+                        offsets.update(file_handle, data.offset);
+                    }
+                    // catch up on anything written while we weren't running
+                    if data.is_behind(len) && !paths.is_empty() {
+                        self.tail(file_handle, &path, &paths, data, budget, callback);
                     }
                 }
-                Event::New(mut entry_ptr) => {
+                Event::New(key) => {
                     Metrics::fs().increment_creates();
                     // similar to initiate but sets the offset to 0
-                    let entry = unsafe { entry_ptr.as_mut() };
+                    let entry = match fs.entry(key) {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    let path = fs.resolve_direct_path(entry);
                     let paths = fs.resolve_valid_paths(entry);
                     if paths.is_empty() {
                         return;
                     }
+                    let id = match entry.file_handle() {
+                        Some(v) => v.id(),
+                        None => return,
+                    };
 
-                    if let Entry::File { ref mut data, file_handle, .. } = entry {
+                    let paths = match self.link_paths(fs, id, &path) {
+                        Some(aliases) => [paths, aliases].concat(),
+                        // another link to the file reads it
+                        None => return,
+                    };
+                    if !self.open_handle(fs, key, &path) {
+                        return;
+                    }
+                    if let Some((file_handle, data)) = open_file(fs, key) {
                         info!("added {:?}", paths[0]);
-                        *data = 0;
-                        self.tail(file_handle, &paths, data, callback);
+                        data.offset = 0;
+                        self.tail(file_handle, &path, &paths, data, budget, callback);
                     }
                 }
-                Event::Write(mut entry_ptr) => {
+                Event::Write(key) => {
                     Metrics::fs().increment_writes();
-                    let entry = unsafe { entry_ptr.as_mut() };
+                    let entry = match fs.entry(key) {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    let path = fs.resolve_direct_path(entry);
                     let paths = fs.resolve_valid_paths(entry);
                     if paths.is_empty() {
                         return;
                     }
+                    let id = match entry.file_handle() {
+                        Some(v) => v.id(),
+                        None => return,
+                    };
 
-                    if let Entry::File { ref mut data, file_handle, .. } = entry {
-                        self.tail(file_handle, &paths, data, callback);
+                    let paths = match self.link_paths(fs, id, &path) {
+                        Some(aliases) => [paths, aliases].concat(),
+                        // another link to the file reads it
+                        None => return,
+                    };
+                    // closed handles are reopened by the first write after they went idle
+                    if !self.open_handle(fs, key, &path) {
+                        return;
+                    }
+                    if let Some((file_handle, data)) = open_file(fs, key) {
+                        self.tail(file_handle, &path, &paths, data, budget, callback);
                     }
                 }
-                Event::Delete(mut entry_ptr) => {
+                Event::Delete(key) => {
                     Metrics::fs().increment_deletes();
-                    let mut entry = unsafe { entry_ptr.as_mut() };
+                    let entry = match fs.entry(key) {
+                        Some(v) => v,
+                        None => return,
+                    };
                     let paths = fs.resolve_valid_paths(entry);
                     if paths.is_empty() {
                         return;
                     }
 
-                    if let Entry::Symlink { link, .. } = entry {
-                        if let Some(real_entry) = fs.lookup(link) {
-                            entry = unsafe { &mut *real_entry.as_ptr() };
-                        } else {
-                            error!("can't wrap up deleted symlink - pointed to file / directory doesn't exist: {:?}", paths[0]);
+                    // only the file itself is gone when it's symlink is deleted
+                    let via_symlink = entry.link().is_some();
+                    let key = match entry {
+                        Entry::Symlink { link, .. } => match fs.lookup(link) {
+                            Some(real_key) => real_key,
+                            None => {
+                                error!("can't wrap up deleted symlink - pointed to file / directory doesn't exist: {:?}", paths[0]);
+                                key
+                            }
+                        },
+                        _ => key,
+                    };
+
+                    let entry = match fs.entry(key) {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    let path = fs.resolve_direct_path(entry);
+                    let id = match entry.file_handle() {
+                        Some(v) => v.id(),
+                        None => return,
+                    };
+
+                    let paths = match self.link_paths(fs, id, &path) {
+                        Some(aliases) => [paths, aliases].concat(),
+                        // another link to the file reads it, so there is nothing to wrap up
+                        None => {
+                            if !via_symlink {
+                                self.forget_link(id, &path);
+                            }
+                            return;
+                        }
+                    };
+                    // a closed handle has nothing left to read, it was closed after going idle
+                    // and the file can't be reopened once it's gone
+                    if let Some((file_handle, data)) = open_file(fs, key) {
+                        // the file won't get another turn, so read everything that's left
+                        let unbounded = ReadBudget::default();
+                        self.tail(file_handle, &path, &paths, data, unbounded, callback);
+                    }
+                    if !via_symlink {
+                        self.open.remove(&key);
+                        // another link to the file carries on from where this one left off
+                        let next = self.forget_link(id, &path);
+                        if let Some(next) = next.and_then(|next| fs.lookup(&next)) {
+                            let state =
+                                fs.entry_mut(key).and_then(Entry::data_mut).map(std::mem::take);
+                            if let (Some(state), Some(next_data)) =
+                                (state, fs.entry_mut(next).and_then(Entry::data_mut))
+                            {
+                                *next_data = state;
+                            }
                         }
                     }
 
-                    if let Entry::File { ref mut data, file_handle, .. } = entry {
-                        self.tail(file_handle, &paths, data, callback);
+                    let (handle, data) = match fs.entry_mut(key) {
+                        Some(Entry::File {
+                            file_handle, data, ..
+                        }) => (file_handle, data),
+                        _ => return,
+                    };
+                    let file_handle = match handle.get() {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    // only forget the offset once the last link to the file is gone
+                    let unlinked = file_handle.metadata().map(|m| m.nlink() == 0).unwrap_or(true);
+                    // a writer can keep appending to an unlinked file it still has open
+                    let drains = self.config.delete_grace.is_some() && !via_symlink;
+                    if unlinked && drains {
+                        if let Some(file) = handle.take() {
+                            debug!("draining deleted file {:?}", path);
+                            let now = Instant::now();
+                            self.draining.push(Draining {
+                                file,
+                                path,
+                                paths,
+                                state: std::mem::take(data),
+                                deleted: now,
+                                last_read: now,
+                            });
+                        }
+                        return;
+                    }
+
+                    self.flush_pending(file_handle, callback);
+                    if let (true, Some(offsets)) = (unlinked, self.offsets.as_mut()) {
+                        offsets.remove(file_handle);
                     }
                 }
             };
         });
+
+        for path in backlog {
+            // files read on an event this time round already had their turn
+            if !self.served.contains(&path) {
+                self.tail_backlogged(&path, callback);
+            }
+        }
+
+        self.drain_deleted(callback);
+        self.tail_discovered(callback);
+        self.close_idle();
+        self.flush_expired(callback);
+        if let Some(offsets) = self.offsets.as_mut() {
+            offsets.flush_if_due();
+        }
     }
 
-    // tail a file for new line(s)
-    fn tail<F>(&mut self, file_handle: &File, paths: &[PathBuf], offset: &mut u64, callback: &mut F)
+    // reads deleted files again, releasing those that went quiet or are past their grace period
+    fn drain_deleted<F>(&mut self, callback: &mut F)
     where
         F: FnMut(Vec<LineBuilder>),
     {
-        // get the file len
-        let len = match file_handle.metadata().map(|m| m.len()) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("unable to stat {:?}: {:?}", &paths[0], e);
-                return;
+        let grace = self.config.delete_grace.unwrap_or_default();
+        for mut draining in std::mem::take(&mut self.draining) {
+            // the file can't get another event so it's read to the end every time
+            let start = draining.state.offset;
+            self.tail(
+                &draining.file,
+                &draining.path,
+                &draining.paths,
+                &mut draining.state,
+                ReadBudget::default(),
+                callback,
+            );
+            if draining.state.offset != start {
+                draining.last_read = Instant::now();
+            }
+
+            if draining.deleted.elapsed() < grace
+                && draining.last_read.elapsed() < DRAIN_QUIET_PERIOD
+            {
+                self.draining.push(draining);
+                continue;
+            }
+
+            debug!("releasing deleted file {:?}", draining.path);
+            self.flush_pending(&draining.file, callback);
+            if let Some(offsets) = self.offsets.as_mut() {
+                offsets.remove(&draining.file);
+            }
+        }
+    }
+
+    // scans /proc for files opened in containers when a scan is due, then reads every file found
+    fn tail_discovered<F>(&mut self, callback: &mut F)
+    where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        let config = match self.config.discovery.as_ref() {
+            Some(v) => v,
+            None => return,
+        };
+        let due = match self.last_discovery {
+            Some(last) => last.elapsed() >= config.interval,
+            None => true,
+        };
+        if due {
+            self.last_discovery = Some(Instant::now());
+            let found = discovery::scan(&config.proc_dir, self.fs.borrow().rules());
+            self.update_discovered(found, callback);
+        }
+
+        let budget = self.config.budget;
+        let mut discovered = std::mem::take(&mut self.discovered);
+        for file in discovered.values_mut() {
+            let (path, paths) = (&file.host_path, &file.paths);
+            self.tail(&file.file, path, paths, &mut file.state, budget, callback);
+        }
+        self.discovered = discovered;
+    }
+
+    // starts reading the newly found files and releases those no process has open anymore
+    fn update_discovered<F>(&mut self, found: Vec<OpenFile>, callback: &mut F)
+    where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        // files in the watched directories are read from there
+        let tailed = self.fs.borrow().file_ids();
+        let mut seen = HashSet::new();
+        for open in found {
+            let file = match File::open(&open.host_path) {
+                Ok(v) => v,
+                Err(e) => {
+                    debug!("unable to open {:?}: {:?}", open.host_path, e);
+                    continue;
+                }
+            };
+            let metadata = match file.metadata() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            // a file open in several processes is attributed to the first one found
+            let id = FileId::from_metadata(&metadata);
+            if tailed.contains(&id) || !seen.insert(id) || self.discovered.contains_key(&id) {
+                continue;
+            }
+
+            info!(
+                "discovered {:?} open in {} ({})",
+                open.path, open.comm, open.pid
+            );
+            let mut state = FileState::default();
+            let restored = self.offsets.as_mut().and_then(|o| o.restore(&file));
+            state.offset = match restored {
+                Some(offset) => offset,
+                None if self.config.initial_position.reads_from_start(&metadata) => 0,
+                None => metadata.len(),
+            };
+            state.fingerprint = Fingerprint::new(&file).ok();
+            state.meta.insert("comm".into(), Value::from(open.comm));
+            state.meta.insert("cgroup".into(), Value::from(open.cgroup));
+            self.discovered.insert(
+                id,
+                Discovered {
+                    file,
+                    host_path: open.host_path,
+                    paths: vec![open.path],
+                    state,
+                },
+            );
+        }
+
+        let closed: Vec<_> = self
+            .discovered
+            .keys()
+            .filter(|id| !seen.contains(*id))
+            .cloned()
+            .collect();
+        for id in closed {
+            if let Some(mut closed) = self.discovered.remove(&id) {
+                if tailed.contains(&id) {
+                    debug!(
+                        "releasing {:?}, it's read as a watched file",
+                        closed.paths[0]
+                    );
+                    continue;
+                }
+                debug!("releasing {:?}, it's no longer open", closed.paths[0]);
+                // whatever was written before the file was closed is still sent
+                let (path, paths) = (&closed.host_path, &closed.paths);
+                let unbounded = ReadBudget::default();
+                self.tail(
+                    &closed.file,
+                    path,
+                    paths,
+                    &mut closed.state,
+                    unbounded,
+                    callback,
+                );
+                self.flush_pending(&closed.file, callback);
             }
+        }
+    }
+
+    // gives a file that ran out of budget on an earlier call another turn
+    fn tail_backlogged<F>(&mut self, path: &PathBuf, callback: &mut F)
+    where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        let fs = self.fs.clone();
+        let mut fs = fs.borrow_mut();
+        // the file has been deleted, and read to the end, since it's last turn
+        let key = match fs.lookup(path) {
+            Some(v) => v,
+            None => return,
+        };
+        let entry = &fs[key];
+        let paths = fs.resolve_valid_paths(entry);
+        if paths.is_empty() {
+            return;
+        }
+        let id = match entry.file_handle() {
+            Some(v) => v.id(),
+            None => return,
         };
 
-        // if we are at the end of the file there's no work to do
-        if *offset == len {
+        let paths = match self.link_paths(&fs, id, path) {
+            Some(aliases) => [paths, aliases].concat(),
+            None => return,
+        };
+        if !self.open_handle(&mut fs, key, path) {
             return;
         }
-        // open the file, create a reader
-        let mut reader = BufReader::new(file_handle);
-        // if the offset is greater than the file's len
-        // it's very likely a truncation occurred
-        if *offset > len {
-            info!("{:?} was truncated from {} to {}", &paths[0], *offset, len);
-            *offset = if len < 8192 { 0 } else { len };
+        if let Some((file_handle, data)) = open_file(&mut fs, key) {
+            let budget = self.config.budget;
+            self.tail(file_handle, path, &paths, data, budget, callback);
+        }
+    }
+
+    // with link dedupe on, returns the paths of the other links to a file so it's lines are sent
+    // under them as well, or None if another link is the one that reads the file
+    fn link_paths(
+        &mut self,
+        fs: &FileSystem<FileState>,
+        id: FileId,
+        path: &PathBuf,
+    ) -> Option<Vec<PathBuf>> {
+        if !self.config.dedupe_links {
+            return Some(Vec::new());
+        }
+
+        let links = self.links.entry(id).or_default();
+        // forget links that were deleted or renamed without an event reaching us
+        links.retain(|link| link == path || is_link(fs, link, id));
+        if !links.contains(path) {
+            links.push(path.clone());
+        }
+        if links[0] != *path {
+            return None;
+        }
+
+        let mut paths = Vec::new();
+        for link in links[1..].iter() {
+            if let Some(key) = fs.lookup(link) {
+                paths.extend(fs.resolve_valid_paths(&fs[key]));
+            }
+        }
+        Some(paths)
+    }
+
+    // forgets a deleted link to a file, returning the link that reads the file from now on
+    fn forget_link(&mut self, id: FileId, path: &PathBuf) -> Option<PathBuf> {
+        let links = self.links.get_mut(&id)?;
+        links.retain(|link| link != path);
+        let next = links.first().cloned();
+        if next.is_none() {
+            self.links.remove(&id);
+        }
+        next
+    }
+
+    // opens the handle of a file if it's closed, closing the least recently read files if that
+    // puts too many files open, returns false if the file can't be opened
+    fn open_handle(
+        &mut self,
+        fs: &mut FileSystem<FileState>,
+        key: EntryKey<FileState>,
+        path: &PathBuf,
+    ) -> bool {
+        self.open.insert(key, Instant::now());
+        let (handle, state) = match fs.entry_mut(key) {
+            Some(Entry::File {
+                file_handle, data, ..
+            }) => (file_handle, data),
+            _ => return false,
+        };
+        if handle.is_open() {
+            return true;
+        }
+
+        match handle.reopen(path) {
+            Ok(true) => {}
+            Ok(false) => {
+                info!(
+                    "{:?} was replaced while it's handle was closed, reading from the start",
+                    path
+                );
+                *state = FileState::default();
+            }
+            Err(e) => {
+                warn!("unable to open {:?}: {:?}", path, e);
+                self.open.remove(&key);
+                return false;
+            }
+        }
+
+        if let Some(max_open_files) = self.config.max_open_files {
+            while self.open.len() > max_open_files.max(1) {
+                let lru = self
+                    .open
+                    .iter()
+                    .filter(|(other, _)| **other != key)
+                    .min_by_key(|(_, last_read)| **last_read)
+                    .map(|(other, _)| *other);
+                match lru {
+                    Some(lru) => close_handle(fs, &mut self.open, lru),
+                    None => break,
+                }
+            }
+        }
+        Metrics::fs().set_open_files(self.open.len() as u64);
+        true
+    }
+
+    // closes the handles of files that haven't been read within the idle timeout
+    fn close_idle(&mut self) {
+        let idle_timeout = match self.config.idle_timeout {
+            Some(v) => v,
+            None => return,
+        };
+        let idle: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, last_read)| last_read.elapsed() >= idle_timeout)
+            .map(|(key, _)| *key)
+            .collect();
+        if idle.is_empty() {
             return;
         }
-        // seek to the offset, this creates the "tailing" effect
-        if let Err(e) = reader.seek(SeekFrom::Start(*offset)) {
-            error!("error seeking {:?}", e);
+
+        let fs = self.fs.clone();
+        let mut fs = fs.borrow_mut();
+        for key in idle {
+            if let Some(entry) = fs.entry(key) {
+                debug!("closing idle file {:?}", fs.resolve_direct_path(entry));
+            }
+            close_handle(&mut fs, &mut self.open, key);
+        }
+        Metrics::fs().set_open_files(self.open.len() as u64);
+    }
+
+    // tail a file for new line(s) within the budget, recording the new offset if it moved and
+    // queuing the file for another turn if the budget ran out
+    fn tail<F>(
+        &mut self,
+        file_handle: &File,
+        direct_path: &PathBuf,
+        paths: &[PathBuf],
+        state: &mut FileState,
+        budget: ReadBudget,
+        callback: &mut F,
+    ) where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        self.served.insert(direct_path.clone());
+        let start = state.offset;
+        let truncated = match state.fingerprint {
+            Some(fingerprint) => !fingerprint.matches(file_handle),
+            None => false,
+        };
+        if truncated {
+            info!(
+                "{:?} was truncated or overwritten, reading from the start",
+                &paths[0]
+            );
+            Metrics::fs().increment_truncations();
+            state.offset = 0;
+            state.format = None;
+            state.encoding = None;
+            state.frame = None;
+            state.fingerprint = None;
+            state.partial = Partial::default();
+            state.decoder = None;
+            // lines held back from the old content are complete, as is the old fingerprint
+            self.flush_pending(file_handle, callback);
+            if let Some(offsets) = self.offsets.as_mut() {
+                offsets.remove(file_handle);
+            }
+        }
+
+        if state.format.is_none() {
+            state.format = Format::detect(file_handle);
+        }
+
+        let key = FileId::new(file_handle)
+            .ok()
+            .map(|id| (id, paths[0].clone()));
+        let reported = self.config.path_map.apply(&paths[0]);
+        let path = reported.to_str().unwrap_or("");
+
+        if let (Some(format), None) = (state.format, state.encoding) {
+            let configured = self.config.encodings.iter().find(|r| r.matches(path));
+            let framing = self.config.framing.iter().find(|r| r.matches(path));
+            // a byte order mark is only visible at the start of plain files
+            let detected = match format {
+                Format::Plain => encoding::detect_bom(file_handle),
+                Format::Compressed(_) | Format::Binary => None,
+            };
+            // UTF-16 and the like are full of NUL bytes, so only files without a known encoding or
+            // framing are checked, this happens once for each file so the skip is only logged once
+            let forced = self.config.binary_include.passes(path) == Status::Ok;
+            if format == Format::Plain
+                && detected.is_none()
+                && configured.is_none()
+                && framing.is_none()
+                && !forced
+                && encoding::is_binary(file_handle)
+            {
+                info!("skipping binary file {:?}", path);
+                state.format = Some(Format::Binary);
+            }
+            let encoding = detected
+                .or_else(|| configured.map(|r| r.encoding()))
+                .unwrap_or(UTF_8);
+            state.encoding = Some(encoding);
+            state.frame = Some(match framing {
+                Some(rule) => rule.framing().encode(encoding),
+                None => Framing::Newline.encode(encoding),
+            });
+        }
+        if state.format == Some(Format::Binary) {
             return;
         }
+        let rule = self.config.multiline.iter().position(|r| r.matches(path));
+        let format = self.config.formats.iter().find(|r| r.matches(path));
+        let format = format.map(|r| r.format());
 
-        loop {
-            let mut raw_line = Vec::new();
-            // read until a new line returning the line length
-            let line_len = match reader.read_until(b'\n', &mut raw_line) {
-                Ok(v) => v as u64,
-                Err(e) => {
-                    error!("error reading from file {:?}: {:?}", &paths[0], e);
-                    return;
+        let multiline = &self.config.multiline;
+        let config = &self.config;
+        let pending = &mut self.pending;
+        let mut partial = std::mem::take(&mut state.partial);
+        let file_meta = std::mem::take(&mut state.meta);
+        let mut on_record = |record: Record| {
+            let Record {
+                line,
+                mut meta,
+                len: line_len,
+                offset: line_offset,
+            } = record;
+            meta.extend(file_meta.iter().map(|(k, v)| (k.clone(), v.clone())));
+            let (rule, key) = match (rule, &key) {
+                (Some(rule), Some(key)) => (rule, key),
+                _ => return send_line(paths, config, line, &meta, line_len, callback),
+            };
+
+            let accepted = match pending.get(key) {
+                Some(event) => event.accepts(multiline, &line),
+                None => false,
+            };
+            if !accepted {
+                if let Some(event) = pending.remove(key) {
+                    send_event(event, config, callback);
                 }
+                let event = PendingEvent::new(rule, paths, line_offset).meta(meta);
+                pending.insert(key.clone(), event);
+            }
+            if let Some(event) = pending.get_mut(key) {
+                event.push(paths, line, line_len);
+            }
+        };
+        let mut on_line = |line: String, line_len: u64, line_offset: u64| match format {
+            // nothing is passed on while the rest of the message is still to come
+            Some(format) => {
+                format.decode(line, line_len, line_offset, &mut partial, &mut on_record)
+            }
+            None => on_record(Record {
+                line,
+                meta: Map::new(),
+                len: line_len,
+                offset: line_offset,
+            }),
+        };
+
+        let more = match state.format {
+            // compressed files are decompressed from the start, so one that is still being written
+            // is left for a later turn rather than decompressed again on every write
+            Some(Format::Compressed(_))
+                if state.offset != COMPLETE
+                    && state.decoder.is_none()
+                    && is_being_written(file_handle) =>
+            {
+                debug!("deferring {:?} until it's fully written", &paths[0]);
+                true
+            }
+            Some(Format::Compressed(compression)) => read_compressed(
+                compression,
+                file_handle,
+                paths,
+                state,
+                &self.config,
+                budget,
+                &mut on_line,
+            ),
+            _ => read_lines(
+                file_handle,
+                paths,
+                state,
+                &self.config,
+                budget,
+                &mut on_line,
+            ),
+        };
+        state.partial = partial;
+        state.meta = file_meta;
+        if more && !self.backlog.contains(direct_path) {
+            self.backlog.push_back(direct_path.clone());
+        }
+
+        // keep extending the fingerprint until it covers the full head of the file
+        match state.fingerprint {
+            Some(fingerprint) if fingerprint.is_complete() => {}
+            _ => state.fingerprint = Fingerprint::new(file_handle).ok(),
+        }
+
+        if state.offset != start || truncated {
+            // lines held back for multiline events haven't been sent yet, so they must be re-read
+            // after a restart
+            let stored = match key.as_ref().and_then(|key| self.pending.get(key)) {
+                Some(event) => event.start_offset(),
+                None => state.offset,
             };
-            // try to parse the raw data as utf8
-            // if that fails replace invalid chars with blank chars
-            // see String::from_utf8_lossy docs
-            let mut line = String::from_utf8(raw_line)
-                .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).to_string());
+            // as must the start of a split message
+            let stored = match state.partial.start_offset() {
+                Some(offset) => offset.min(stored),
+                None => stored,
+            };
+            if let Some(offsets) = self.offsets.as_mut() {
+                offsets.update(file_handle, stored);
+            }
+        }
+    }
+
+    // send any multiline events for a file that are still waiting on more lines
+    fn flush_pending<F>(&mut self, file_handle: &File, callback: &mut F)
+    where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        let id = match FileId::new(file_handle) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        let keys: Vec<_> = self.pending.keys().filter(|k| k.0 == id).cloned().collect();
+        for key in keys {
+            if let Some(event) = self.pending.remove(&key) {
+                send_event(event, &self.config, callback);
+            }
+        }
+    }
+
+    // send any multiline events that haven't seen a new line within their timeout
+    fn flush_expired<F>(&mut self, callback: &mut F)
+    where
+        F: FnMut(Vec<LineBuilder>),
+    {
+        let multiline = &self.config.multiline;
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, event)| event.is_expired(multiline))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            if let Some(event) = self.pending.remove(&key) {
+                if let Some(offsets) = self.offsets.as_mut() {
+                    offsets.set_offset(&key.0, event.end_offset());
+                }
+                send_event(event, &self.config, callback);
+            }
+        }
+    }
+}
+
+// returns true if path is a hard link to the file id
+fn is_link(fs: &FileSystem<FileState>, path: &PathBuf, id: FileId) -> bool {
+    match fs.lookup(path).map(|key| &fs[key]) {
+        Some(Entry::File { file_handle, .. }) => file_handle.id() == id,
+        _ => false,
+    }
+}
+
+// returns true if a file was written to within the compressed quiet period and hasn't been deleted,
+// a deleted file can't get another turn so it's read straight away
+fn is_being_written(file_handle: &File) -> bool {
+    let metadata = match file_handle.metadata() {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    if metadata.nlink() == 0 {
+        return false;
+    }
+    match metadata.modified().map(|modified| modified.elapsed()) {
+        Ok(Ok(elapsed)) => elapsed < COMPRESSED_QUIET_PERIOD,
+        _ => false,
+    }
+}
+
+// returns the open file and the state of a file entry, None if the entry is gone or it's handle is
+// closed
+fn open_file(
+    fs: &mut FileSystem<FileState>,
+    key: EntryKey<FileState>,
+) -> Option<(&File, &mut FileState)> {
+    match fs.entry_mut(key)? {
+        Entry::File {
+            file_handle, data, ..
+        } => Some((file_handle.get()?, data)),
+        _ => None,
+    }
+}
+
+// closes the handle of a file, it's reopened the next time the file is read
+fn close_handle(
+    fs: &mut FileSystem<FileState>,
+    open: &mut HashMap<EntryKey<FileState>, Instant>,
+    key: EntryKey<FileState>,
+) {
+    open.remove(&key);
+    if let Some(Entry::File {
+        file_handle, data, ..
+    }) = fs.entry_mut(key)
+    {
+        file_handle.close();
+        // the decoder holds a handle of it's own
+        data.decoder = None;
+    }
+}
+
+// read complete line(s) from the offset onwards until the budget is spent, passing each line, it's
+// length and the offset it started at to on_line, returns true if there is more to read
+fn read_lines(
+    file_handle: &File,
+    paths: &[PathBuf],
+    state: &mut FileState,
+    config: &TailConfig,
+    budget: ReadBudget,
+    on_line: &mut dyn FnMut(String, u64, u64),
+) -> bool {
+    // get the file len
+    let metadata = match file_handle.metadata() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("unable to stat {:?}: {:?}", &paths[0], e);
+            return false;
+        }
+    };
+    let len = metadata.len();
+
+    // if we are at the end of the file there's no work to do
+    if state.offset == len {
+        return false;
+    }
+    // open the file, create a reader
+    let mut reader = BufReader::new(file_handle);
+    // if the offset is greater than the file's len
+    // it's very likely a truncation occurred
+    if state.offset > len {
+        info!(
+            "{:?} was truncated from {} to {}",
+            &paths[0], state.offset, len
+        );
+        Metrics::fs().increment_truncations();
+        state.offset = if config.initial_position.reads_from_start(&metadata) {
+            0
+        } else {
+            len
+        };
+        state.oversize = false;
+        return false;
+    }
+    // seek to the offset, this creates the "tailing" effect
+    if let Err(e) = reader.seek(SeekFrom::Start(state.offset)) {
+        error!("error seeking {:?}", e);
+        return false;
+    }
+
+    let limit = config.line_limit;
+    let encoding = state.encoding.unwrap_or(UTF_8);
+    let frame = frame_of(state, encoding);
+    let start = state.offset;
+    let mut lines = 0;
+    loop {
+        if budget.is_spent(state.offset - start, lines) {
+            return true;
+        }
+        let mut raw_line = Vec::new();
+        // read until the end of a record or the line limit returning the number of bytes read
+        let (line_len, line_end) = match read_record(&mut reader, &mut raw_line, limit, &frame) {
+            Ok((len, line_end)) => (len as u64, line_end),
+            Err(e) => {
+                error!("error reading from file {:?}: {:?}", &paths[0], e);
+                return false;
+            }
+        };
+        let oversize = &mut state.oversize;
+        let framed = frame_line(raw_line, line_end, limit, oversize, encoding, &frame);
+        let line = match framed {
+            Framed::Line(line) => line,
+            Framed::Skipped => {
+                state.offset += line_len;
+                continue;
+            }
             // if the line doesn't end with a new line we might have read in the middle of a write
             // so we return in this case
-            if !line.ends_with('\n') {
+            Framed::Partial(_) => {
                 Metrics::fs().increment_partial_reads();
-                return;
-            }
-            // remove the trailing new line
-            line.pop();
-            // increment the offset
-            *offset += line_len;
-            // send the line upstream, safe to unwrap
-            debug!("tailer sendings lines for {:?}", paths);
-            callback(
-                paths
+                return false;
+            }
+        };
+        // increment the offset
+        let line_offset = state.offset;
+        state.offset += line_len;
+        lines += 1;
+        on_line(line, line_len, line_offset);
+    }
+}
+
+// decompress a file from the offset, passing each new line to on_line until the budget is spent,
+// returns true if there is more to read, the decoder is kept in the state for the next turn in that
+// case, otherwise the file is decompressed from the start skipping the offset bytes that were
+// already read
+fn read_compressed(
+    compression: Compression,
+    file_handle: &File,
+    paths: &[PathBuf],
+    state: &mut FileState,
+    config: &TailConfig,
+    budget: ReadBudget,
+    on_line: &mut dyn FnMut(String, u64, u64),
+) -> bool {
+    let decoder = state.decoder.take();
+    if state.offset == COMPLETE {
+        return false;
+    }
+
+    let mut decoder = match decoder {
+        Some(decoder) if decoder.offset == state.offset => decoder,
+        _ => match Decoder::new(compression, file_handle) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("unable to decompress {:?}: {:?}", &paths[0], e);
+                return false;
+            }
+        },
+    };
+    let reader = &mut decoder.reader;
+
+    // a truncated stream can't be told apart from a corrupt one, so decoding errors are treated
+    // as the file still being written and the file is retried on it's next write
+    let skip = state.offset - decoder.offset;
+    if let Err(e) = io::copy(&mut reader.take(skip), &mut io::sink()) {
+        debug!("unable to decompress {:?} yet: {:?}", &paths[0], e);
+        return false;
+    }
+
+    let limit = config.line_limit;
+    let encoding = state.encoding.unwrap_or(UTF_8);
+    let frame = frame_of(state, encoding);
+    let start = state.offset;
+    let mut lines = 0;
+    loop {
+        if budget.is_spent(state.offset - start, lines) {
+            decoder.offset = state.offset;
+            state.decoder = Some(decoder);
+            return true;
+        }
+        let mut raw_line = Vec::new();
+        let (line_len, line_end) = match read_record(reader, &mut raw_line, limit, &frame) {
+            Ok((0, _)) => {
+                info!("finished reading compressed file {:?}", &paths[0]);
+                state.offset = COMPLETE;
+                return false;
+            }
+            Ok((len, line_end)) => (len as u64, line_end),
+            Err(e) => {
+                debug!("unable to decompress {:?} yet: {:?}", &paths[0], e);
+                Metrics::fs().increment_partial_reads();
+                return false;
+            }
+        };
+
+        let line_offset = state.offset;
+        state.offset += line_len;
+        let oversize = &mut state.oversize;
+        match frame_line(raw_line, line_end, limit, oversize, encoding, &frame) {
+            // new lines trailing the last length prefixed record
+            Framed::Partial(ref line) if line.is_empty() => {}
+            // the last line of a stream doesn't need to end with a new line
+            Framed::Line(line) | Framed::Partial(line) => {
+                lines += 1;
+                on_line(line, line_len, line_offset)
+            }
+            Framed::Skipped => {}
+        }
+    }
+}
+
+// how a chunk read by read_line ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineEnd {
+    // the end of a record, including it's delimiter
+    NewLine,
+    // the line is longer than the line limit
+    Limit,
+    // the end of the data, more may still be written
+    Eof,
+    // a record that can't be read, skipped up to the next new line
+    Invalid,
+}
+
+// the frame the records of a file are read with
+fn frame_of(state: &FileState, encoding: &'static Encoding) -> Frame {
+    match state.frame.as_ref() {
+        Some(frame) => frame.clone(),
+        None => Framing::Newline.encode(encoding),
+    }
+}
+
+// reads a record as framed by frame, see read_line and read_prefixed
+fn read_record<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+    frame: &Frame,
+) -> io::Result<(usize, LineEnd)> {
+    match frame.delimiter.as_ref() {
+        Some(delimiter) => read_line(reader, buf, limit, delimiter, frame.width, frame.utf8),
+        None => read_prefixed(reader, buf, limit),
+    }
+}
+
+// reads until a delimiter made up of code units width bytes wide, or until the line limit is
+// reached, appending to buf and returning the number of bytes read and how the chunk ends, UTF-8
+// chunks are cut before a character the limit would split
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+    delimiter: &[u8],
+    width: usize,
+    utf8: bool,
+) -> io::Result<(usize, LineEnd)> {
+    // limit to whole code units so multi byte encodings aren't cut mid character
+    let max_bytes = limit.map_or(usize::MAX, |l| {
+        (l.max_bytes - l.max_bytes % width).max(width)
+    });
+    let mut read = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok((read, LineEnd::Eof));
+        }
+
+        let end = if delimiter.len() == 1 {
+            available
+                .iter()
+                .position(|b| *b == delimiter[0])
+                .map(|i| i + 1)
+        } else {
+            find_delimiter(buf, available, delimiter, width)
+        };
+        match end {
+            // a line of exactly max_bytes still fits along with it's delimiter
+            Some(end) if buf.len() + end - delimiter.len() <= max_bytes => {
+                buf.extend_from_slice(&available[..end]);
+                reader.consume(end);
+                return Ok((read + end, LineEnd::NewLine));
+            }
+            _ if buf.len() >= max_bytes => return Ok((read, LineEnd::Limit)),
+            _ => {
+                let mut used = available.len().min(max_bytes - buf.len());
+                let cut = match buf.len() + used == max_bytes && utf8 {
+                    true => cut_character(buf, available, max_bytes),
+                    false => None,
+                };
+                if let Some((start, len)) = cut {
+                    if start > buf.len() || (start == buf.len() && start > 0) {
+                        // the character starts the next chunk instead
+                        let used = start - buf.len();
+                        buf.extend_from_slice(&available[..used]);
+                        reader.consume(used);
+                        return Ok((read + used, LineEnd::Limit));
+                    }
+                    // the character was already started, or is longer than the limit, so it's
+                    // kept whole past the limit
+                    used = (start + len - buf.len()).min(available.len());
+                }
+                buf.extend_from_slice(&available[..used]);
+                reader.consume(used);
+                read += used;
+            }
+        }
+    }
+}
+
+// returns where the UTF-8 character at the end of the first end bytes of buf followed by available
+// starts along with it's length, None unless the character is cut short at end
+fn cut_character(buf: &[u8], available: &[u8], end: usize) -> Option<(usize, usize)> {
+    let byte_at = |i: usize| match buf.get(i) {
+        Some(b) => *b,
+        None => available[i - buf.len()],
+    };
+    // characters are at most 4 bytes long, anything else isn't valid UTF-8 and is left as is
+    let start = (end.saturating_sub(4)..end)
+        .rev()
+        .find(|i| byte_at(*i) & 0b1100_0000 != 0b1000_0000)?;
+    let len = match byte_at(start) {
+        b if b >= 0b1111_0000 => 4,
+        b if b >= 0b1110_0000 => 3,
+        b if b >= 0b1100_0000 => 2,
+        _ => 1,
+    };
+    if start + len > end {
+        Some((start, len))
+    } else {
+        None
+    }
+}
+
+// returns the length of available up to and including the first delimiter that is aligned to the
+// code units of the line started in buf, the delimiter may start in buf
+fn find_delimiter(buf: &[u8], available: &[u8], delimiter: &[u8], width: usize) -> Option<usize> {
+    let len = delimiter.len();
+    let byte_at = |i: usize| match buf.get(i) {
+        Some(b) => *b,
+        None => available[i - buf.len()],
+    };
+    // delimiters ending inside buf were looked for when buf was read
+    let earliest = (buf.len() + 1).saturating_sub(len);
+    let mut start = earliest + (width - earliest % width) % width;
+    while start + len <= buf.len() + available.len() {
+        if (0..len).all(|i| byte_at(start + i) == delimiter[i]) {
+            return Some(start + len - buf.len());
+        }
+        start += width;
+    }
+    None
+}
+
+// reads a record preceded by it's length, in ASCII digits followed by a space, appending the
+// record to buf and returning the number of bytes read including the length and how the record
+// ends, records over the line limit are cut at it with the rest of the record dropped
+//
+// A length that can't be parsed is skipped along with everything up to the next new line, where
+// reading picks up again with the next record.
+fn read_prefixed<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    limit: Option<LineLimit>,
+) -> io::Result<(usize, LineEnd)> {
+    let mut prefix = Vec::new();
+    let read = (&mut *reader)
+        .take(MAX_PREFIX_LEN as u64)
+        .read_until(b' ', &mut prefix)?;
+    if prefix.last() != Some(&b' ') {
+        if read < MAX_PREFIX_LEN {
+            return Ok((read, LineEnd::Eof));
+        }
+        return skip_invalid(reader, &prefix);
+    }
+    // records are often followed by a new line that isn't part of their length
+    let digits = std::str::from_utf8(&prefix[..read - 1])
+        .map(|digits| digits.trim_start_matches(&['\n', '\r'][..]));
+    let len: usize = match digits.ok().and_then(|digits| digits.parse().ok()) {
+        Some(v) => v,
+        None => return skip_invalid(reader, &prefix),
+    };
+
+    let kept = len.min(limit.map_or(usize::MAX, |l| l.max_bytes));
+    let mut record = (&mut *reader).take(kept as u64).read_to_end(buf)?;
+    if record == kept && kept < len {
+        let mut rest = (&mut *reader).take((len - kept) as u64);
+        record += io::copy(&mut rest, &mut io::sink())? as usize;
+    }
+    if record < len {
+        return Ok((read + record, LineEnd::Eof));
+    }
+    if kept < len {
+        Metrics::fs().increment_oversize_lines();
+    }
+    Ok((read + len, LineEnd::NewLine))
+}
+
+// skips the rest of the line an invalid length prefix was read from, returning the number of bytes
+// skipped including the prefix, the skip is redone from the prefix until the new line is written
+fn skip_invalid<R: BufRead>(reader: &mut R, prefix: &[u8]) -> io::Result<(usize, LineEnd)> {
+    let mut rest = Vec::new();
+    let skipped = prefix.len() + reader.read_until(b'\n', &mut rest)?;
+    if rest.last() != Some(&b'\n') {
+        return Ok((skipped, LineEnd::Eof));
+    }
+    warn!(
+        "skipped {} bytes after invalid record length {:?}",
+        skipped,
+        String::from_utf8_lossy(prefix)
+    );
+    Metrics::fs().increment_invalid_records();
+    Ok((skipped, LineEnd::Invalid))
+}
+
+// a chunk of bytes read by read_line after applying the line limit
+enum Framed {
+    // a line to send, without it's delimiter
+    Line(String),
+    // the end of the data without a delimiter, more may still be written
+    Partial(String),
+    // the dropped remainder of a truncated line
+    Skipped,
+}
+
+// turns a chunk read by read_line into a UTF-8 line, splitting or truncating it if it's over the
+// limit
+fn frame_line(
+    mut raw_line: Vec<u8>,
+    line_end: LineEnd,
+    limit: Option<LineLimit>,
+    oversize: &mut bool,
+    encoding: &'static Encoding,
+    frame: &Frame,
+) -> Framed {
+    if line_end == LineEnd::Invalid {
+        return Framed::Skipped;
+    }
+    // length prefixed records can be empty
+    if raw_line.is_empty() && line_end != LineEnd::NewLine {
+        return Framed::Partial(String::new());
+    }
+
+    if *oversize && limit.map(|l| l.mode) == Some(OversizeMode::Truncate) {
+        // keep dropping bytes until the line ends
+        *oversize = line_end != LineEnd::NewLine;
+        return Framed::Skipped;
+    }
+
+    match line_end {
+        LineEnd::NewLine => {
+            // remove the delimiter, along with the trailer before it
+            let delimiter_len = frame.delimiter.as_ref().map_or(0, Vec::len);
+            raw_line.truncate(raw_line.len() - delimiter_len);
+            if let Some(trailer) = frame.trailer.as_ref() {
+                if raw_line.ends_with(trailer) {
+                    raw_line.truncate(raw_line.len() - trailer.len());
+                }
+            }
+            *oversize = false;
+            Framed::Line(encoding::decode(encoding, raw_line))
+        }
+        LineEnd::Limit => {
+            if !*oversize {
+                Metrics::fs().increment_oversize_lines();
+            }
+            *oversize = true;
+            let mut line = encoding::decode(encoding, raw_line);
+            if limit.map(|l| l.mode) == Some(OversizeMode::Truncate) {
+                line.push_str(TRUNCATED_MARKER);
+            }
+            Framed::Line(line)
+        }
+        LineEnd::Eof => Framed::Partial(encoding::decode(encoding, raw_line)),
+        LineEnd::Invalid => Framed::Skipped,
+    }
+}
+
+// send a line upstream once for each path it was read from, or once for the first path if links
+// are deduped, with the fields taken from that path
+fn send_line<F>(
+    paths: &[PathBuf],
+    config: &TailConfig,
+    line: String,
+    meta: &Map<String, Value>,
+    line_len: u64,
+    callback: &mut F,
+) where
+    F: FnMut(Vec<LineBuilder>),
+{
+    debug!("tailer sendings lines for {:?}", paths);
+    let (paths, aliases) = match paths.split_first() {
+        Some((path, aliases)) if config.dedupe_links => (std::slice::from_ref(path), aliases),
+        _ => (paths, &[][..]),
+    };
+    callback(
+        paths
+            .iter()
+            .map(|path| {
+                Metrics::fs().increment_lines();
+                Metrics::fs().add_bytes(line_len);
+                let path = config.path_map.apply(path);
+                let path = path.to_str().unwrap_or("");
+                let mut builder = LineBuilder::new().line(line.clone()).file(path.to_string());
+                for (key, value) in meta {
+                    builder = fields::insert_meta(builder, key.as_str(), value.clone());
+                }
+                if !aliases.is_empty() {
+                    let aliases = aliases
+                        .iter()
+                        .map(|alias| config.path_map.apply(alias))
+                        .map(|alias| Value::from(alias.to_str().unwrap_or("")))
+                        .collect();
+                    builder = fields::insert_meta(builder, ALIASES_KEY, Value::Array(aliases));
+                }
+                config
+                    .path_fields
                     .iter()
-                    .map(|path| {
-                        Metrics::fs().increment_lines();
-                        Metrics::fs().add_bytes(line_len);
-                        LineBuilder::new()
-                            .line(line.clone())
-                            .file(path.to_str().unwrap_or("").to_string())
-                    })
-                    .collect(),
+                    .fold(builder, |builder, rule| rule.apply(path, builder))
+            })
+            .collect(),
+    );
+}
+
+// send a completed multiline event upstream
+fn send_event<F>(event: PendingEvent, config: &TailConfig, callback: &mut F)
+where
+    F: FnMut(Vec<LineBuilder>),
+{
+    let (line, meta, paths, line_len) = event.into_line();
+    send_line(&paths, config, line, &meta, line_len, callback);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::LineFormat;
+    use crate::rule::GlobRule;
+    use flate2::write::GzEncoder;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+    use tempfile::TempDir;
+
+    fn read_all_compressed(
+        compression: Compression,
+        file_handle: &File,
+        offset: &mut u64,
+    ) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut state = FileState {
+            offset: *offset,
+            ..Default::default()
+        };
+        read_compressed(
+            compression,
+            file_handle,
+            &[PathBuf::from("test")],
+            &mut state,
+            &TailConfig::default(),
+            ReadBudget::default(),
+            &mut |line, _, _| lines.push(line),
+        );
+        *offset = state.offset;
+        lines
+    }
+
+    fn read_all_lines(path: &PathBuf, config: &TailConfig) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut state = FileState::default();
+        read_lines(
+            &File::open(path).unwrap(),
+            std::slice::from_ref(path),
+            &mut state,
+            config,
+            ReadBudget::default(),
+            &mut |line, _, _| lines.push(line),
+        );
+        lines
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn tail_compressed_gzip() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log.1.gz");
+        File::create(&path)
+            .unwrap()
+            .write_all(&gzip(b"first\nsecond\nlast"))
+            .unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        assert_eq!(
+            Format::detect(&file_handle),
+            Some(Format::Compressed(Compression::Gzip))
+        );
+
+        let mut offset = 0;
+        let lines = read_all_compressed(Compression::Gzip, &file_handle, &mut offset);
+        assert_eq!(lines, vec!["first", "second", "last"]);
+        assert_eq!(offset, COMPLETE);
+
+        // completed files are never read again
+        let lines = read_all_compressed(Compression::Gzip, &file_handle, &mut offset);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn tail_compressed_zstd() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log.1.zst");
+        let data = zstd::stream::encode_all(&b"first\nsecond\n"[..], 0).unwrap();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        assert_eq!(
+            Format::detect(&file_handle),
+            Some(Format::Compressed(Compression::Zstd))
+        );
+
+        let mut offset = 0;
+        let lines = read_all_compressed(Compression::Zstd, &file_handle, &mut offset);
+        assert_eq!(lines, vec!["first", "second"]);
+        assert_eq!(offset, COMPLETE);
+    }
+
+    #[test]
+    fn tail_compressed_partially_written() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log.1.gz");
+        let content: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let data = gzip(content.as_bytes());
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&data[..data.len() / 2]).unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        let mut offset = 0;
+        let mut lines = read_all_compressed(Compression::Gzip, &file_handle, &mut offset);
+        assert_ne!(offset, COMPLETE);
+
+        file.write_all(&data[data.len() / 2..]).unwrap();
+        lines.append(&mut read_all_compressed(
+            Compression::Gzip,
+            &file_handle,
+            &mut offset,
+        ));
+        assert_eq!(offset, COMPLETE);
+        assert_eq!(lines.len(), 1000);
+        assert_eq!(lines[999], "line 999");
+    }
+
+    #[test]
+    fn tail_compressed_budget_keeps_decoder() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log.1.gz");
+        let data = gzip(b"first\nsecond\nthird\n");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        let mut state = FileState::default();
+        let budget = ReadBudget {
+            bytes: None,
+            lines: Some(2),
+        };
+        let mut lines = Vec::new();
+        let mut read = |state: &mut FileState| {
+            read_compressed(
+                Compression::Gzip,
+                &file_handle,
+                std::slice::from_ref(&path),
+                state,
+                &TailConfig::default(),
+                budget,
+                &mut |line, _, _| lines.push(line),
+            )
+        };
+        assert!(read(&mut state));
+        assert_eq!(state.decoder.as_ref().map(|d| d.offset), Some(13));
+
+        // the next turn carries on with the decoder, without decompressing the file again
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .write_all_at(&vec![0; data.len()], 0)
+            .unwrap();
+        assert!(!read(&mut state));
+        assert!(state.decoder.is_none());
+        assert_eq!(state.offset, COMPLETE);
+        assert_eq!(lines, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn tail_compressed_deferred_until_quiet() {
+        let tempdir = TempDir::new().unwrap();
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+        tailer.process(&mut callback);
+
+        let path = tempdir.path().join("test.log.1.gz");
+        let data = gzip(b"first\nsecond\n");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&data[..data.len() / 2]).unwrap();
+        tailer.process(&mut callback);
+        file.write_all(&data[data.len() / 2..]).unwrap();
+        tailer.process(&mut callback);
+        // left for later rather than decompressed on each write
+        assert!(tailer.backlog.contains(&path));
+
+        // backdate the file as if it hadn't been written to for a while
+        let times = [libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        }; 2];
+        assert_eq!(
+            unsafe { libc::futimens(file.as_raw_fd(), times.as_ptr()) },
+            0
+        );
+        tailer.process(&mut callback);
+        assert_eq!(lines, vec!["first", "second"]);
+        assert!(tailer.backlog.is_empty());
+    }
+
+    #[test]
+    fn tail_parse_initial_position() {
+        assert_eq!("start".parse(), Ok(InitialPosition::Start));
+        assert_eq!(" END ".parse(), Ok(InitialPosition::End));
+        assert_eq!(
+            "threshold(8192)".parse(),
+            Ok(InitialPosition::Threshold(8192))
+        );
+        assert_eq!(
+            "lookback(2h)".parse(),
+            Ok(InitialPosition::Lookback(Duration::from_secs(7200)))
+        );
+        assert_eq!(
+            "lookback(90)".parse(),
+            Ok(InitialPosition::Lookback(Duration::from_secs(90)))
+        );
+        assert!("threshold".parse::<InitialPosition>().is_err());
+        assert!("lookback(2w)".parse::<InitialPosition>().is_err());
+        assert!("middle".parse::<InitialPosition>().is_err());
+    }
+
+    #[test]
+    fn tail_initial_position_reads_from_start() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        File::create(&path)
+            .unwrap()
+            .write_all(&[b'a'; 100])
+            .unwrap();
+        let metadata = path.metadata().unwrap();
+
+        assert!(InitialPosition::Start.reads_from_start(&metadata));
+        assert!(!InitialPosition::End.reads_from_start(&metadata));
+        assert!(InitialPosition::Threshold(101).reads_from_start(&metadata));
+        assert!(!InitialPosition::Threshold(100).reads_from_start(&metadata));
+        assert!(InitialPosition::Lookback(Duration::from_secs(3600)).reads_from_start(&metadata));
+        assert!(!InitialPosition::Lookback(Duration::from_secs(0)).reads_from_start(&metadata));
+    }
+
+    #[test]
+    fn tail_truncated_uses_initial_position() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        File::create(&path).unwrap().write_all(b"new\n").unwrap();
+        let file_handle = File::open(&path).unwrap();
+        let paths = [path.clone()];
+
+        for &(initial_position, expected) in
+            [(InitialPosition::End, 4), (InitialPosition::Start, 0)].iter()
+        {
+            let config = TailConfig {
+                initial_position,
+                ..Default::default()
+            };
+            let mut state = FileState {
+                offset: 100,
+                ..Default::default()
+            };
+            read_lines(
+                &file_handle,
+                &paths,
+                &mut state,
+                &config,
+                ReadBudget::default(),
+                &mut |_, _, _| {},
+            );
+            assert_eq!(state.offset, expected);
+        }
+    }
+
+    #[test]
+    fn tail_oversize_lines() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        File::create(&path)
+            .unwrap()
+            .write_all(b"12345\n1234567890123\nabc\n")
+            .unwrap();
+
+        let limit = |mode| TailConfig {
+            line_limit: Some(LineLimit { max_bytes: 5, mode }),
+            ..Default::default()
+        };
+        let oversize_lines = Metrics::fs().read_oversize_lines();
+        assert_eq!(
+            read_all_lines(&path, &limit(OversizeMode::Truncate)),
+            vec!["12345", "12345 [truncated]", "abc"]
+        );
+        assert_eq!(
+            read_all_lines(&path, &limit(OversizeMode::Split)),
+            vec!["12345", "12345", "67890", "123", "abc"]
+        );
+        assert!(Metrics::fs().read_oversize_lines() >= oversize_lines + 2);
+        assert_eq!(
+            read_all_lines(&path, &TailConfig::default()),
+            vec!["12345", "1234567890123", "abc"]
+        );
+    }
+
+    #[test]
+    fn tail_oversize_line_partially_written() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"1234567").unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        let paths = [path.clone()];
+        let config = TailConfig {
+            line_limit: Some(LineLimit {
+                max_bytes: 3,
+                mode: OversizeMode::Truncate,
+            }),
+            ..Default::default()
+        };
+        let mut state = FileState::default();
+        let mut lines = Vec::new();
+
+        read_lines(
+            &file_handle,
+            &paths,
+            &mut state,
+            &config,
+            ReadBudget::default(),
+            &mut |line, _, _| lines.push(line),
+        );
+        // the rest of the line is dropped as it's written
+        file.write_all(b"89\nnext\n").unwrap();
+        read_lines(
+            &file_handle,
+            &paths,
+            &mut state,
+            &config,
+            ReadBudget::default(),
+            &mut |line, _, _| lines.push(line),
+        );
+
+        assert_eq!(lines, vec!["123 [truncated]", "nex [truncated]"]);
+        assert_eq!(state.offset, 15);
+    }
+
+    #[test]
+    fn tail_detects_truncate_and_refill() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"aaaa\nbbbb\n").unwrap();
+
+        let mut tailer = Tailer::new(
+            vec![tempdir.path().to_path_buf()],
+            Rules::new(),
+            TailConfig::default(),
+        );
+        let file_handle = File::open(&path).unwrap();
+        let paths = [path.clone()];
+        let mut state = FileState::default();
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+
+        tailer.tail(
+            &file_handle,
+            &path,
+            &paths,
+            &mut state,
+            ReadBudget::default(),
+            &mut callback,
+        );
+        assert_eq!(state.offset, 10);
+
+        // copytruncate followed by more writes than were read before the next event
+        let truncations = Metrics::fs().read_truncations();
+        file.set_len(0).unwrap();
+        file.write_all_at(b"cccc\ndddd\neeee\n", 0).unwrap();
+        tailer.tail(
+            &file_handle,
+            &path,
+            &paths,
+            &mut state,
+            ReadBudget::default(),
+            &mut callback,
+        );
+
+        assert_eq!(state.offset, 15);
+        assert!(Metrics::fs().read_truncations() > truncations);
+        assert_eq!(lines, vec!["aaaa", "bbbb", "cccc", "dddd", "eeee"]);
+    }
+
+    #[test]
+    fn tail_budget_round_robin() {
+        let tempdir = TempDir::new().unwrap();
+        File::create(tempdir.path().join("noisy.log"))
+            .unwrap()
+            .write_all(b"n1\nn2\nn3\nn4\nn5\n")
+            .unwrap();
+        File::create(tempdir.path().join("quiet.log"))
+            .unwrap()
+            .write_all(b"q1\n")
+            .unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            budget: ReadBudget {
+                bytes: None,
+                lines: Some(2),
+            },
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+
+        tailer.process(&mut callback);
+        tailer.process(&mut callback);
+        tailer.process(&mut callback);
+        tailer.process(&mut callback);
+
+        let mut first_drain = lines[..3].to_vec();
+        first_drain.sort();
+        assert_eq!(first_drain, vec!["n1", "n2", "q1"]);
+        assert_eq!(lines[3..], ["n3", "n4", "n5"]);
+        assert!(tailer.backlog.is_empty());
+    }
+
+    #[test]
+    fn tail_closes_idle_and_excess_handles() {
+        let tempdir = TempDir::new().unwrap();
+        for name in ["a.log", "b.log", "c.log"].iter() {
+            File::create(tempdir.path().join(name))
+                .unwrap()
+                .write_all(b"first\n")
+                .unwrap();
+        }
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            max_open_files: Some(2),
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+
+        tailer.process(&mut callback);
+        assert_eq!(tailer.open.len(), 2);
+
+        // everything is idle straight away
+        tailer.config.idle_timeout = Some(Duration::from_secs(0));
+        tailer.process(&mut callback);
+        assert!(tailer.open.is_empty());
+
+        // the next write reopens the file
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(tempdir.path().join("b.log"))
+            .unwrap();
+        file.write_all(b"second\n").unwrap();
+        tailer.config.idle_timeout = None;
+        tailer.process(&mut callback);
+        assert_eq!(tailer.open.len(), 1);
+        assert_eq!(lines, vec!["first", "first", "first", "second"]);
+    }
+
+    #[test]
+    fn tail_open_handles_follow_renames() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("a.log");
+        File::create(&path).unwrap().write_all(b"first\n").unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+        tailer.process(&mut callback);
+        assert_eq!(tailer.open.len(), 1);
+
+        let renamed = tempdir.path().join("b.log");
+        std::fs::rename(&path, &renamed).unwrap();
+        tailer.process(&mut callback);
+        OpenOptions::new()
+            .append(true)
+            .open(&renamed)
+            .unwrap()
+            .write_all(b"second\n")
+            .unwrap();
+        tailer.process(&mut callback);
+        assert_eq!(tailer.open.len(), 1);
+
+        std::fs::remove_file(&renamed).unwrap();
+        tailer.process(&mut callback);
+        assert!(tailer.open.is_empty());
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn tail_drains_deleted_file() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"first\n").unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            delete_grace: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback =
+            |batch: Vec<LineBuilder>| lines.extend(batch.into_iter().map(|l| l.line.unwrap()));
+        tailer.process(&mut callback);
+
+        // the writer keeps appending after the file is deleted
+        std::fs::remove_file(&path).unwrap();
+        tailer.process(&mut callback);
+        assert_eq!(tailer.draining.len(), 1);
+        file.write_all(b"second\n").unwrap();
+        tailer.process(&mut callback);
+        assert_eq!(tailer.draining.len(), 1);
+
+        // the grace period is over
+        tailer.config.delete_grace = Some(Duration::from_secs(0));
+        file.write_all(b"third\n").unwrap();
+        tailer.process(&mut callback);
+        assert!(tailer.draining.is_empty());
+        assert_eq!(lines, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn tail_dedupe_links() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("a.log");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"first\n").unwrap();
+        std::os::unix::fs::symlink(&path, tempdir.path().join("b.log")).unwrap();
+        std::fs::hard_link(&path, tempdir.path().join("c.log")).unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            dedupe_links: true,
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+
+        tailer.process(&mut callback);
+        file.write_all(b"second\n").unwrap();
+        tailer.process(&mut callback);
+        // the link that reads the file is deleted, the other one carries on
+        std::fs::remove_file(&path).unwrap();
+        tailer.process(&mut callback);
+        file.write_all(b"third\n").unwrap();
+        tailer.process(&mut callback);
+
+        let text: Vec<_> = lines.iter().map(|l| l.line.clone().unwrap()).collect();
+        assert_eq!(text, vec!["first", "second", "third"]);
+        // the hard link is only known about once it's initialized, after the first line is sent
+        let mut paths: Vec<_> = match lines[1].meta {
+            Some(ref meta) => meta["aliases"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|alias| alias.as_str().unwrap().to_string())
+                .collect(),
+            None => panic!("expected aliases"),
+        };
+        paths.push(lines[1].file.clone().unwrap());
+        paths.sort();
+        let expected: Vec<_> = ["a.log", "b.log", "c.log"]
+            .iter()
+            .map(|name| tempdir.path().join(name).to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn tail_skips_binary_files() {
+        let tempdir = TempDir::new().unwrap();
+        for name in ["text.log", "core.log", "forced.log"].iter() {
+            File::create(tempdir.path().join(name)).unwrap();
+        }
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let mut binary_include = Rules::new();
+        binary_include.add_inclusion(GlobRule::new("*/forced.log").unwrap());
+        let config = TailConfig {
+            binary_include,
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+        tailer.process(&mut callback);
+
+        let append = |name: &str, data: &[u8]| {
+            OpenOptions::new()
+                .append(true)
+                .open(tempdir.path().join(name))
+                .unwrap()
+                .write_all(data)
+                .unwrap();
+        };
+        append("text.log", b"text\n");
+        append("core.log", b"\x7fELF\x02\x01\x01\0\0\nbinary\n");
+        append("forced.log", b"forced\0\n");
+        tailer.process(&mut callback);
+        // the skip sticks once a file is classified
+        append("core.log", b"more\n");
+        tailer.process(&mut callback);
+
+        let mut text: Vec<_> = lines.iter().map(|l| l.line.clone().unwrap()).collect();
+        text.sort();
+        assert_eq!(text, vec!["forced\u{0}", "text"]);
+    }
+
+    #[test]
+    fn tail_remapped_paths() {
+        let tempdir = TempDir::new().unwrap();
+        let mounted = tempdir.path().join("host/var/log");
+        std::fs::create_dir_all(&mounted).unwrap();
+        File::create(mounted.join("a.log")).unwrap();
+        File::create(mounted.join("skip.log")).unwrap();
+
+        // rules are written against the reported paths
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("/var/log/*.log").unwrap());
+        rules.add_exclusion(GlobRule::new("/var/log/skip.log").unwrap());
+        let mut path_map = PathMap::new();
+        path_map.add(tempdir.path().join("host"), "/");
+        let config = TailConfig {
+            path_map,
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![mounted.clone()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+        tailer.process(&mut callback);
+
+        for name in ["a.log", "skip.log"].iter() {
+            let mut file = OpenOptions::new()
+                .append(true)
+                .open(mounted.join(name))
+                .unwrap();
+            writeln!(file, "{}", name).unwrap();
+        }
+        tailer.process(&mut callback);
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line, Some("a.log".into()));
+        assert_eq!(lines[0].file, Some("/var/log/a.log".into()));
+    }
+
+    #[test]
+    fn tail_discovered_files() {
+        let tempdir = TempDir::new().unwrap();
+        let watched = tempdir.path().join("watched");
+        let container = tempdir.path().join("container");
+        let process = tempdir.path().join("proc/42");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::create_dir_all(container.join("srv")).unwrap();
+        std::fs::create_dir_all(process.join("fd")).unwrap();
+        std::fs::write(container.join("srv/app.log"), "before\n").unwrap();
+        std::os::unix::fs::symlink(&container, process.join("root")).unwrap();
+        std::fs::write(process.join("comm"), "app\n").unwrap();
+        std::fs::write(process.join("cgroup"), "0::/docker/abc\n").unwrap();
+        std::os::unix::fs::symlink("/srv/app.log", process.join("fd/3")).unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            discovery: Some(DiscoveryConfig {
+                proc_dir: tempdir.path().join("proc"),
+                interval: Duration::from_secs(0),
+            }),
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![watched], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+        tailer.process(&mut callback);
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(container.join("srv/app.log"))
+            .unwrap();
+        writeln!(file, "during").unwrap();
+        tailer.process(&mut callback);
+        // the file gets a last read once the process closes it
+        std::fs::remove_file(process.join("fd/3")).unwrap();
+        writeln!(file, "after").unwrap();
+        tailer.process(&mut callback);
+        writeln!(file, "unseen").unwrap();
+        tailer.process(&mut callback);
+
+        let text: Vec<_> = lines.iter().map(|l| l.line.clone().unwrap()).collect();
+        assert_eq!(text, vec!["before", "during", "after"]);
+        assert_eq!(lines[0].file, Some("/srv/app.log".into()));
+        assert_eq!(
+            lines[0].meta,
+            Some(serde_json::json!({"comm": "app", "cgroup": "/docker/abc"}))
+        );
+    }
+
+    #[test]
+    fn tail_discovered_files_already_watched() {
+        let tempdir = TempDir::new().unwrap();
+        let container = tempdir.path().join("container");
+        let watched = container.join("srv");
+        let process = tempdir.path().join("proc/42");
+        std::fs::create_dir_all(&watched).unwrap();
+        std::fs::create_dir_all(process.join("fd")).unwrap();
+        std::fs::write(watched.join("app.log"), "before\n").unwrap();
+        std::os::unix::fs::symlink(&container, process.join("root")).unwrap();
+        std::fs::write(process.join("comm"), "app\n").unwrap();
+        std::fs::write(process.join("cgroup"), "0::/docker/abc\n").unwrap();
+        std::os::unix::fs::symlink("/srv/app.log", process.join("fd/3")).unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let config = TailConfig {
+            initial_position: InitialPosition::Start,
+            discovery: Some(DiscoveryConfig {
+                proc_dir: tempdir.path().join("proc"),
+                interval: Duration::from_secs(0),
+            }),
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![watched.clone()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+        tailer.process(&mut callback);
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(watched.join("app.log"))
+            .unwrap();
+        writeln!(file, "during").unwrap();
+        tailer.process(&mut callback);
+
+        // the file is only read through the watched directory
+        assert!(tailer.discovered.is_empty());
+        let text: Vec<_> = lines.iter().map(|l| l.line.clone().unwrap()).collect();
+        assert_eq!(text, vec!["before", "during"]);
+        assert!(lines.iter().all(|l| l.meta.is_none()));
+    }
+
+    #[test]
+    fn tail_docker_format() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("abc-json.log");
+        File::create(&path).unwrap();
+
+        let mut rules = Rules::new();
+        rules.add_inclusion(GlobRule::new("*.log").unwrap());
+        let mut docker = Rules::new();
+        docker.add_inclusion(GlobRule::new("*-json.log").unwrap());
+        let config = TailConfig {
+            formats: vec![FormatRule::new(docker, LineFormat::Docker)],
+            ..Default::default()
+        };
+        let mut tailer = Tailer::new(vec![tempdir.path().to_path_buf()], rules, config);
+        let mut lines = Vec::new();
+        let mut callback = |batch: Vec<LineBuilder>| lines.extend(batch);
+        tailer.process(&mut callback);
+
+        let append = |data: &str| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            writeln!(file, "{}", data).unwrap();
+        };
+        append(r#"{"log":"split ","stream":"stderr","time":"2020-06-01T12:00:00Z"}"#);
+        tailer.process(&mut callback);
+        append(r#"{"log":"line\n","stream":"stderr","time":"2020-06-01T12:00:01Z"}"#);
+        tailer.process(&mut callback);
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line, Some("split line".into()));
+        assert_eq!(
+            lines[0].meta,
+            Some(serde_json::json!({"stream": "stderr", "time": "2020-06-01T12:00:00Z"}))
+        );
+    }
+
+    #[test]
+    fn tail_budget_bytes() {
+        let budget = ReadBudget {
+            bytes: Some(10),
+            lines: None,
+        };
+        assert!(!budget.is_spent(9, 100));
+        assert!(budget.is_spent(10, 0));
+        assert!(!ReadBudget::default().is_spent(u64::MAX, u64::MAX));
+    }
+
+    fn utf16le(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn tail_utf16_lines() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        // U+010A is encoded as 0a 01 so it must not be mistaken for a new line
+        let mut data = b"\xff\xfe".to_vec();
+        data.extend(utf16le("first \u{10a}\nsecond\npart"));
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let file_handle = File::open(&path).unwrap();
+        let mut state = FileState {
+            encoding: encoding::detect_bom(&file_handle),
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        read_lines(
+            &file_handle,
+            &[path],
+            &mut state,
+            &TailConfig::default(),
+            ReadBudget::default(),
+            &mut |line, _, _| lines.push(line),
+        );
+
+        assert_eq!(lines, vec!["first \u{10a}", "second"]);
+        assert_eq!(state.offset, data.len() as u64 - 8);
+    }
+
+    #[test]
+    fn tail_framing_modes() {
+        let tempdir = TempDir::new().unwrap();
+        let path = tempdir.path().join("test.log");
+        let read = |data: &[u8], framing: Framing| {
+            File::create(&path).unwrap().write_all(data).unwrap();
+            let mut lines = Vec::new();
+            let mut state = FileState {
+                frame: Some(framing.encode(UTF_8)),
+                ..Default::default()
+            };
+            read_lines(
+                &File::open(&path).unwrap(),
+                std::slice::from_ref(&path),
+                &mut state,
+                &TailConfig::default(),
+                ReadBudget::default(),
+                &mut |line, _, _| lines.push(line),
+            );
+            (lines, state.offset)
+        };
+
+        let (lines, _) = read(b"a\r\nb\n\r\n", Framing::Newline);
+        assert_eq!(lines, vec!["a", "b", ""]);
+        let (lines, offset) = read(b"a\r\nb\nc\r\nd\r", Framing::CrLf);
+        assert_eq!(lines, vec!["a", "b\nc"]);
+        assert_eq!(offset, 8);
+        let (lines, _) = read(b"a\r\n\0b\0", Framing::Nul);
+        assert_eq!(lines, vec!["a\r\n", "b"]);
+        let (lines, _) = read(
+            b"a\x1e\x1eb\x1e\x1ec\x1e",
+            Framing::delimiter("\x1e\x1e").unwrap(),
+        );
+        assert_eq!(lines, vec!["a", "b"]);
+
+        let (lines, offset) = read(b"3 a\nb0 \n5 c\r\n\n\n4 de", Framing::LengthPrefixed);
+        assert_eq!(lines, vec!["a\nb", "", "c\r\n\n\n"]);
+        assert_eq!(offset, 15);
+        // records carry on after the new line following an invalid length
+        let invalid_records = Metrics::fs().read_invalid_records();
+        let (lines, offset) = read(b"2 ab\nx ab\n2 cd\n3 ef", Framing::LengthPrefixed);
+        assert_eq!(lines, vec!["ab", "cd"]);
+        assert_eq!(offset, 14);
+        assert!(Metrics::fs().read_invalid_records() > invalid_records);
+        let (lines, offset) = read(b"2 ab\nx ab", Framing::LengthPrefixed);
+        assert_eq!(lines, vec!["ab"]);
+        assert_eq!(offset, 4);
+    }
+
+    #[test]
+    fn tail_read_line_split_delimiter() {
+        let data = utf16le("ab\r\ncd\r\n");
+        let delimiter = Framing::CrLf
+            .encode(encoding_rs::UTF_16LE)
+            .delimiter
+            .unwrap();
+        // small buffers split the delimiter across reads
+        for capacity in 1..8 {
+            let mut reader = BufReader::with_capacity(capacity, &data[..]);
+            let mut buf = Vec::new();
+            let read = read_line(&mut reader, &mut buf, None, &delimiter, 2, false).unwrap();
+            assert_eq!(read, (8, LineEnd::NewLine));
+            assert_eq!(buf, utf16le("ab\r\n"));
+        }
+    }
+
+    #[test]
+    fn tail_read_line_split_code_units() {
+        let data = utf16le("ab\ncd\n");
+        // an odd buffer size splits code units across reads
+        let mut reader = BufReader::with_capacity(3, &data[..]);
+        let newline = Framing::Newline
+            .encode(encoding_rs::UTF_16LE)
+            .delimiter
+            .unwrap();
+
+        let mut buf = Vec::new();
+        let read = read_line(&mut reader, &mut buf, None, &newline, 2, false).unwrap();
+        assert_eq!(read, (6, LineEnd::NewLine));
+        assert_eq!(buf, utf16le("ab\n"));
+
+        let mut buf = Vec::new();
+        let limit = Some(LineLimit {
+            max_bytes: 3,
+            mode: OversizeMode::Split,
+        });
+        let read = read_line(&mut reader, &mut buf, limit, &newline, 2, false).unwrap();
+        assert_eq!(read, (2, LineEnd::Limit));
+        assert_eq!(buf, utf16le("c"));
+    }
+
+    #[test]
+    fn tail_read_line_split_utf8() {
+        let data = "aé€b\n".as_bytes();
+        let limit = Some(LineLimit {
+            max_bytes: 2,
+            mode: OversizeMode::Split,
+        });
+        // chunks end before a character the limit cuts, a character longer than the limit is kept
+        // whole
+        for capacity in [2, 64].iter() {
+            let mut reader = BufReader::with_capacity(*capacity, data);
+            let mut chunks = Vec::new();
+            loop {
+                let mut buf = Vec::new();
+                let read = read_line(&mut reader, &mut buf, limit, b"\n", 1, true).unwrap();
+                if read.0 == 0 {
+                    break;
+                }
+                chunks.push((String::from_utf8(buf).unwrap(), read.1));
+            }
+            assert_eq!(
+                chunks,
+                vec![
+                    ("a".to_string(), LineEnd::Limit),
+                    ("é".to_string(), LineEnd::Limit),
+                    ("€".to_string(), LineEnd::Limit),
+                    ("b\n".to_string(), LineEnd::NewLine),
+                ]
             );
         }
     }
@@ -1,6 +1,7 @@
 use std::mem::replace;
 use std::time::{Duration, Instant};
 
+use chrono::DateTime;
 use tokio::prelude::Future;
 use tokio::runtime::{Builder, Runtime};
 
@@ -14,6 +15,10 @@ use crate::types::response::Response;
 use metrics::Metrics;
 use std::sync::Arc;
 
+/// The meta key holding the time a line was written at as an RFC 3339 timestamp, lines carrying it
+/// are sent with it as their timestamp instead of the time they are sent at
+pub const TIME_KEY: &str = "time";
+
 /// Http(s) client used to send logs to the Ingest API
 pub struct Client {
     inner: HttpClient,
@@ -66,7 +71,11 @@ impl Client {
     /// The main logic loop, consumes self because it should only be called once
     pub fn send(&mut self, line: LineBuilder) {
         self.poll();
-        if let Ok(line) = line.build() {
+        let written_at = written_at(&line);
+        if let Ok(mut line) = line.build() {
+            if let Some(timestamp) = written_at {
+                line.timestamp = timestamp;
+            }
             self.buffer_bytes += line.line.len();
             self.buffer.push(line);
         }
@@ -131,3 +140,42 @@ impl Client {
         self.runtime.spawn(fut);
     }
 }
+
+// the time a line was written at in seconds, if it's meta carries it
+fn written_at(line: &LineBuilder) -> Option<i64> {
+    let time = line.meta.as_ref()?.get(TIME_KEY)?.as_str()?;
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn client_written_at() {
+        let line = |meta| LineBuilder::new().line("hello").meta(meta);
+        // docker writes the time in UTC with nanoseconds
+        assert_eq!(
+            written_at(&line(json!({"time": "2020-06-01T12:00:00.000000001Z"}))),
+            Some(1_591_012_800)
+        );
+        // CRI runtimes write it with nanoseconds too, in the node's offset for CRI-O
+        assert_eq!(
+            written_at(&line(json!({"time": "2020-06-01T12:00:00.123456789Z"}))),
+            Some(1_591_012_800)
+        );
+        assert_eq!(
+            written_at(&line(
+                json!({"time": "2020-06-01T14:00:00.123456789+02:00"})
+            )),
+            Some(1_591_012_800)
+        );
+        assert_eq!(written_at(&line(json!({"time": "yesterday"}))), None);
+        assert_eq!(written_at(&line(json!({"time": 1_591_012_800}))), None);
+        assert_eq!(written_at(&line(json!({"stream": "stdout"}))), None);
+        assert_eq!(written_at(&LineBuilder::new().line("hello")), None);
+    }
+}
@@ -86,6 +86,15 @@ impl Metrics {
                 "lines" => fs.read_lines(),
                 "bytes" => fs.read_bytes(),
                 "partial_reads" => fs.read_partial_reads(),
+                "overflows" => fs.read_overflows(),
+                "truncations" => fs.read_truncations(),
+                "oversize_lines" => fs.read_oversize_lines(),
+                "invalid_records" => fs.read_invalid_records(),
+                "degraded_watches" => fs.read_degraded_watches(),
+                "open_files" => fs.read_open_files(),
+                "scanned_dirs" => fs.read_scanned_dirs(),
+                "scanned_entries" => fs.read_scanned_entries(),
+                "pending_scan_dirs" => fs.read_pending_scan_dirs(),
             },
             "memory" => object!{
                 "active" => memory.read_active(),
@@ -121,6 +130,17 @@ pub struct Fs {
     lines: AtomicU64,
     bytes: AtomicU64,
     partial_reads: AtomicU64,
+    overflows: AtomicU64,
+    truncations: AtomicU64,
+    oversize_lines: AtomicU64,
+    invalid_records: AtomicU64,
+    // a gauge of the paths currently polled because inotify watches ran out, it isn't reset
+    degraded_watches: AtomicU64,
+    open_files: AtomicU64,
+    scanned_dirs: AtomicU64,
+    scanned_entries: AtomicU64,
+    // a gauge of the directories waiting to be listed by the running scan, it isn't reset
+    pending_scan_dirs: AtomicU64,
 }
 
 impl Fs {
@@ -133,6 +153,15 @@ impl Fs {
             lines: AtomicU64::new(0),
             bytes: AtomicU64::new(0),
             partial_reads: AtomicU64::new(0),
+            overflows: AtomicU64::new(0),
+            truncations: AtomicU64::new(0),
+            oversize_lines: AtomicU64::new(0),
+            invalid_records: AtomicU64::new(0),
+            degraded_watches: AtomicU64::new(0),
+            open_files: AtomicU64::new(0),
+            scanned_dirs: AtomicU64::new(0),
+            scanned_entries: AtomicU64::new(0),
+            pending_scan_dirs: AtomicU64::new(0),
         }
     }
 
@@ -144,6 +173,12 @@ impl Fs {
         self.lines.store(0, Ordering::Relaxed);
         self.bytes.store(0, Ordering::Relaxed);
         self.partial_reads.store(0, Ordering::Relaxed);
+        self.overflows.store(0, Ordering::Relaxed);
+        self.truncations.store(0, Ordering::Relaxed);
+        self.oversize_lines.store(0, Ordering::Relaxed);
+        self.invalid_records.store(0, Ordering::Relaxed);
+        self.scanned_dirs.store(0, Ordering::Relaxed);
+        self.scanned_entries.store(0, Ordering::Relaxed);
     }
 
     pub fn increment_events(&self) {
@@ -201,6 +236,78 @@ impl Fs {
     pub fn read_partial_reads(&self) -> u64 {
         self.partial_reads.load(Ordering::Relaxed)
     }
+
+    pub fn increment_overflows(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_truncations(&self) {
+        self.truncations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_truncations(&self) -> u64 {
+        self.truncations.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_oversize_lines(&self) {
+        self.oversize_lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_oversize_lines(&self) -> u64 {
+        self.oversize_lines.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_invalid_records(&self) {
+        self.invalid_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_invalid_records(&self) -> u64 {
+        self.invalid_records.load(Ordering::Relaxed)
+    }
+
+    pub fn set_degraded_watches(&self, num: u64) {
+        self.degraded_watches.store(num, Ordering::Relaxed);
+    }
+
+    pub fn read_degraded_watches(&self) -> u64 {
+        self.degraded_watches.load(Ordering::Relaxed)
+    }
+
+    pub fn set_open_files(&self, num: u64) {
+        self.open_files.store(num, Ordering::Relaxed);
+    }
+
+    pub fn read_open_files(&self) -> u64 {
+        self.open_files.load(Ordering::Relaxed)
+    }
+
+    pub fn increment_scanned_dirs(&self) {
+        self.scanned_dirs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_scanned_dirs(&self) -> u64 {
+        self.scanned_dirs.load(Ordering::Relaxed)
+    }
+
+    pub fn add_scanned_entries(&self, num: u64) {
+        self.scanned_entries.fetch_add(num, Ordering::Relaxed);
+    }
+
+    pub fn read_scanned_entries(&self) -> u64 {
+        self.scanned_entries.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pending_scan_dirs(&self, num: u64) {
+        self.pending_scan_dirs.store(num, Ordering::Relaxed);
+    }
+
+    pub fn read_pending_scan_dirs(&self) -> u64 {
+        self.pending_scan_dirs.load(Ordering::Relaxed)
+    }
 }
 
 pub struct Memory {